@@ -1,15 +1,22 @@
-// swift-tools-version:5.3
+// swift-tools-version:5.5
 import PackageDescription
 
 let package = Package(
     name: "JustDooooooIt",
+    platforms: [
+        .macOS(.v10_13),
+    ],
     products: [
         .executable(name: "JustDooooooIt", targets: ["JustDooooooIt"]),
     ],
     dependencies: [],
     targets: [
-        .target(
+        .executableTarget(
             name: "JustDooooooIt",
             dependencies: []),
+        .testTarget(
+            name: "JustDooooooItTests",
+            dependencies: ["JustDooooooIt"],
+            resources: [.copy("Fixtures")]),
     ]
 )
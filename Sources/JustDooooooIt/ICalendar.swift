@@ -0,0 +1,116 @@
+import Foundation
+
+// MARK: - iCalendar
+// Writes todos as RFC 5545 VTODO components. Open todos are NEEDS-ACTION
+// with RELATED-TO pointing at their parent; archived ones are COMPLETED.
+// UIDs are stable per list and ID so subscribed calendars update in place.
+
+func exportICalendar(_ todoList: TodoList, listName: String) -> String {
+    let stamp = iCalendarDateTime(currentTimestamp())
+    var lines = [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//JustDooooooIt//jdi//EN",
+        "X-WR-CALNAME:\(iCalendarText("jdi: \(listName)"))",
+    ]
+    
+    for item in todoList.orderedItems() {
+        lines.append("BEGIN:VTODO")
+        lines.append("UID:\(iCalendarUID(listName: listName, id: item.id))")
+        lines.append("DTSTAMP:\(stamp)")
+        lines.append("CREATED:\(iCalendarDateTime(item.createdAt))")
+        lines.append("SUMMARY:\(iCalendarText(item.text))")
+        if let parentId = item.parentId {
+            lines.append("RELATED-TO;RELTYPE=PARENT:\(iCalendarUID(listName: listName, id: parentId))")
+        }
+        if let dueDate = item.dueDate {
+            lines.append("DUE;VALUE=DATE:\(dueDate.replacingOccurrences(of: "-", with: ""))")
+        }
+        if let priority = item.priority {
+            lines.append("PRIORITY:\(iCalendarPriority(priority))")
+        }
+        if !item.tags.isEmpty {
+            lines.append("CATEGORIES:\(item.tags.map { iCalendarText(String($0.dropFirst())) }.joined(separator: ","))")
+        }
+        lines.append("STATUS:NEEDS-ACTION")
+        lines.append("END:VTODO")
+    }
+    
+    for task in todoList.completedHistory {
+        let completed = iCalendarDateTime(task.completedAt)
+        lines.append("BEGIN:VTODO")
+        lines.append("UID:jdi-\(listName)-\(task.id)-completed-\(completed)@justdooooooit")
+        lines.append("DTSTAMP:\(stamp)")
+        lines.append("SUMMARY:\(iCalendarText(task.text))")
+        lines.append("STATUS:COMPLETED")
+        lines.append("PERCENT-COMPLETE:100")
+        lines.append("COMPLETED:\(completed)")
+        lines.append("END:VTODO")
+    }
+    
+    lines.append("END:VCALENDAR")
+    return lines.map { iCalendarFold($0) + "\r\n" }.joined()
+}
+
+// Rewrites every calendar file registered with `jdi export --sync`.
+func syncCalendarExports(_ store: TodoStore) {
+    for (name, path) in store.calendarSync {
+        guard let todoList = store.lists[name] else {
+            continue
+        }
+        
+        do {
+            try writeFileAtomically(Data(exportICalendar(todoList, listName: name).utf8), to: path)
+        } catch {
+            printError("Warning: could not update calendar file \(path): \(error)")
+        }
+    }
+}
+
+func iCalendarUID(listName: String, id: UInt32) -> String {
+    return "jdi-\(listName)-\(id)@justdooooooit"
+}
+
+// RFC 5545 uses 1 for highest, 5 for medium and 9 for lowest priority.
+func iCalendarPriority(_ priority: Priority) -> Int {
+    switch priority {
+    case .high: return 1
+    case .medium: return 5
+    case .low: return 9
+    }
+}
+
+// "yyyy-MM-dd HH:mm:ss" (UTC) to the basic UTC form 20240131T093000Z.
+func iCalendarDateTime(_ timestamp: String) -> String {
+    let digits = timestamp.filter { $0.isNumber }
+    guard digits.count == 14 else {
+        return timestamp
+    }
+    return "\(digits.prefix(8))T\(digits.suffix(6))Z"
+}
+
+func iCalendarText(_ text: String) -> String {
+    return text
+        .replacingOccurrences(of: "\\", with: "\\\\")
+        .replacingOccurrences(of: ";", with: "\\;")
+        .replacingOccurrences(of: ",", with: "\\,")
+        .replacingOccurrences(of: "\n", with: "\\n")
+}
+
+// Content lines longer than 75 octets are folded onto continuation lines
+// that start with a space, without splitting a UTF-8 character.
+func iCalendarFold(_ line: String) -> String {
+    var folded = ""
+    var lineLength = 0
+    
+    for character in line {
+        let length = String(character).utf8.count
+        if lineLength + length > 75 {
+            folded += "\r\n "
+            lineLength = 1
+        }
+        folded.append(character)
+        lineLength += length
+    }
+    return folded
+}
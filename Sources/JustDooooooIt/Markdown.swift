@@ -0,0 +1,94 @@
+import Foundation
+
+// MARK: - Markdown
+// Renders the todo tree as nested GitHub-style checklists and reads them
+// back. Open todos are "- [ ]" items indented two spaces per level; the
+// most recently completed ones follow as "- [x]" items with their archived
+// subtasks nested under them.
+
+let markdownRecentCompletedCount = 10
+
+func exportMarkdown(_ todoList: TodoList) -> String {
+    var lines: [String] = []
+    
+    for item in todoList.orderedItems() {
+        let depth = todoList.path(to: item).count - 1
+        let indent = String(repeating: "  ", count: depth)
+        lines.append("\(indent)- [ ] \(item.text)")
+    }
+    
+    for task in todoList.completedHistory.suffix(markdownRecentCompletedCount) {
+        lines.append("- [x] \(task.text)")
+        appendArchivedSubtasks(of: task, parentId: task.id, depth: 1, to: &lines)
+    }
+    
+    return lines.map { $0 + "\n" }.joined()
+}
+
+private func appendArchivedSubtasks(of task: CompletedTask, parentId: UInt32, depth: Int, to lines: inout [String]) {
+    let indent = String(repeating: "  ", count: depth)
+    for subtask in task.archivedChildren(of: parentId) {
+        lines.append("\(indent)- [x] \(subtask.text)")
+        appendArchivedSubtasks(of: task, parentId: subtask.id, depth: depth + 1, to: &lines)
+    }
+}
+
+// Any "- [ ]", "* [ ]" or "+ [ ]" line is a task; other lines are ignored.
+// Nesting follows indentation, with a tab counting as four spaces. Checked
+// items are imported as completed, and everything nested under one, checked
+// or not, is archived as its subtasks, just as completing a todo archives
+// its subtree. Line numbers stand in for the IDs the source does not have.
+func parseMarkdown(_ content: String) -> [ImportedTask] {
+    guard let checklistItem = try? NSRegularExpression(pattern: "^([ \\t]*)[-*+] \\[([ xX])\\] (.*)$") else {
+        return []
+    }
+    
+    var tasks: [ImportedTask] = []
+    // Enclosing items, innermost last. `archiveIndex` points at the
+    // completed task in `tasks` that an item's children are archived into.
+    var parents: [(indent: Int, id: UInt32, archiveIndex: Int?)] = []
+    
+    for (lineNumber, line) in content.components(separatedBy: .newlines).enumerated() {
+        let range = NSRange(line.startIndex..., in: line)
+        guard let match = checklistItem.firstMatch(in: line, options: [], range: range),
+              let indentRange = Range(match.range(at: 1), in: line),
+              let markRange = Range(match.range(at: 2), in: line),
+              let textRange = Range(match.range(at: 3), in: line) else {
+            continue
+        }
+        
+        let text = line[textRange].trimmingCharacters(in: .whitespaces)
+        guard !text.isEmpty else {
+            continue
+        }
+        
+        let indent = line[indentRange].reduce(0) { $0 + ($1 == "\t" ? 4 : 1) }
+        while let last = parents.last, last.indent >= indent {
+            parents.removeLast()
+        }
+        
+        let id = UInt32(lineNumber + 1)
+        if let parent = parents.last, let archiveIndex = parent.archiveIndex {
+            tasks[archiveIndex].archivedSubtasks.append(ArchivedSubtask(id: id, text: text, parentId: parent.id, createdAt: currentTimestamp()))
+            if parent.id == UInt32(tasks[archiveIndex].sourceId) {
+                tasks[archiveIndex].subtaskCount += 1
+            }
+            parents.append((indent, id, archiveIndex))
+            continue
+        }
+        
+        var task = ImportedTask(sourceId: String(id), text: text)
+        task.parentSourceId = parents.last.map { String($0.id) }
+        
+        if line[markRange] == " " {
+            parents.append((indent, id, nil))
+        } else {
+            task.completedAt = currentTimestamp()
+            parents.append((indent, id, tasks.count))
+        }
+        
+        tasks.append(task)
+    }
+    
+    return tasks
+}
@@ -0,0 +1,250 @@
+import Foundation
+
+// MARK: - Schema Migrations
+// The data file records its schema_version. Older files are upgraded on
+// load by running every step after their version in order, on the raw
+// JSON, backing the file up as it was before each step. This keeps the
+// Codable types free of defaults for fields that older files did not have.
+//
+//   0  a single list at the top level (before named lists)
+//   1  named lists, with fields added since left out when unset
+//   2  schema_version recorded and every field written explicitly
+//
+// To change the format, bump currentSchemaVersion and append a step.
+
+let currentSchemaVersion = 2
+
+typealias JSONObject = [String: Any]
+
+struct SchemaMigration {
+    // The version the file is at after this step.
+    let version: Int
+    let summary: String
+    // Rewrites the JSON in place and describes each change it made.
+    let apply: (inout JSONObject) -> [String]
+}
+
+let schemaMigrations: [SchemaMigration] = [
+    SchemaMigration(version: 1, summary: "Move the single list into named lists") { json in
+        let itemCount = (json["items"] as? JSONObject)?.count ?? 0
+        json = [
+            "lists": [TodoStore.defaultListName: json],
+            "current_list": TodoStore.defaultListName,
+        ]
+        return ["The list with \(itemCount) open todo(s) becomes list '\(TodoStore.defaultListName)'"]
+    },
+    SchemaMigration(version: 2, summary: "Record the schema version and write every field explicitly") { json in
+        return fillStoreDefaults(&json)
+    },
+]
+
+// Adds every store and list field a version 2 file must have where it is
+// missing. Safe to run again, which is how a current file with fields
+// removed by hand is repaired instead of being set aside.
+@discardableResult
+func fillStoreDefaults(_ json: inout JSONObject) -> [String] {
+    var changes: [String] = []
+    var lists = json["lists"] as? JSONObject ?? [:]
+    for name in lists.keys.sorted() {
+        guard var list = lists[name] as? JSONObject else {
+            continue
+        }
+        changes += fillListDefaults(&list).map { "List '\(name)': \($0)" }
+        lists[name] = list
+    }
+    json["lists"] = lists
+    
+    if json["current_list"] == nil {
+        json["current_list"] = TodoStore.defaultListName
+        changes.append("Select list '\(TodoStore.defaultListName)'")
+    }
+    if json["calendar_sync"] == nil {
+        json["calendar_sync"] = JSONObject()
+    }
+    if json["trash_retention_days"] == nil {
+        json["trash_retention_days"] = TodoStore.defaultTrashRetentionDays
+        changes.append("Keep deleted todos for \(TodoStore.defaultTrashRetentionDays) days")
+    }
+    return changes
+}
+
+// Adds the list fields introduced before version 2 where they are missing.
+// Also used for the lists saved in the undo journal.
+@discardableResult
+func fillListDefaults(_ list: inout JSONObject) -> [String] {
+    var changes: [String] = []
+    
+    var items = list["items"] as? JSONObject ?? [:]
+    var positioned = 0
+    var tagged = 0
+    for key in items.keys {
+        guard var item = items[key] as? JSONObject else {
+            continue
+        }
+        // Before manual ordering, siblings were listed in ID order.
+        if item["position"] == nil, let id = item["id"] as? Int {
+            item["position"] = id
+            positioned += 1
+        }
+        if item["tags"] == nil, let text = item["text"] as? String {
+            item["tags"] = TodoItem.extractTags(from: text)
+            tagged += 1
+        }
+        items[key] = item
+    }
+    list["items"] = items
+    if positioned > 0 {
+        changes.append("Number \(positioned) todo(s) in ID order")
+    }
+    if tagged > 0 {
+        changes.append("Read tags from the text of \(tagged) todo(s)")
+    }
+    
+    var history = list["completed_history"] as? [JSONObject] ?? []
+    var withoutSubtasks = 0
+    for index in history.indices where history[index]["subtasks"] == nil {
+        history[index]["subtasks"] = [JSONObject]()
+        withoutSubtasks += 1
+    }
+    if withoutSubtasks > 0 {
+        changes.append("Mark \(withoutSubtasks) completed task(s) as having no archived subtasks")
+    }
+    if list["completed_history"] == nil {
+        changes.append("Start an empty completion history")
+    }
+    list["completed_history"] = history
+    
+    if list["completed_count"] == nil {
+        list["completed_count"] = history.count
+        changes.append("Set the completed count to \(history.count)")
+    }
+    if list["trash"] == nil {
+        list["trash"] = [JSONObject]()
+        changes.append("Add an empty trash")
+    }
+    return changes
+}
+
+func parseStoreJSON(_ data: Data) throws -> JSONObject {
+    guard let json = try JSONSerialization.jsonObject(with: data) as? JSONObject else {
+        throw JDIError.storage("Expected a JSON object at the top level")
+    }
+    return json
+}
+
+func schemaVersion(of json: JSONObject) -> Int {
+    if let version = json["schema_version"] as? Int {
+        return version
+    }
+    return json["lists"] == nil ? 0 : 1
+}
+
+// Files from a newer jdi are left untouched rather than misread.
+func checkSchemaVersion(_ version: Int, of filePath: String) throws {
+    guard version <= currentSchemaVersion else {
+        throw JDIError.storage("\(filePath) uses schema version \(version), but this jdi only reads up to version \(currentSchemaVersion). Update jdi to open it")
+    }
+}
+
+// Runs every step the file has not had yet, calling `beforeEachStep` with
+// the version and JSON each one starts from. Returns the steps that ran
+// along with the changes each one made.
+@discardableResult
+func migrateStoreJSON(
+    _ json: inout JSONObject,
+    beforeEachStep: (_ version: Int, _ json: JSONObject) throws -> Void = { _, _ in }
+) rethrows -> [(migration: SchemaMigration, changes: [String])] {
+    var applied: [(migration: SchemaMigration, changes: [String])] = []
+    for migration in schemaMigrations where migration.version > schemaVersion(of: json) {
+        try beforeEachStep(schemaVersion(of: json), json)
+        let changes = migration.apply(&json)
+        json["schema_version"] = migration.version
+        applied.append((migration, changes))
+    }
+    return applied
+}
+
+// Journal snapshots are whole lists, so they get the same list defaults.
+func upgradeJournalJSON(_ json: inout JSONObject) {
+    for stackKey in ["undo", "redo"] {
+        guard var entries = json[stackKey] as? [JSONObject] else {
+            continue
+        }
+        
+        for index in entries.indices {
+            if var snapshot = entries[index]["snapshot"] as? JSONObject {
+                fillListDefaults(&snapshot)
+                entries[index]["snapshot"] = snapshot
+            }
+            if var snapshots = entries[index]["snapshots"] as? JSONObject {
+                for name in snapshots.keys {
+                    guard var snapshot = snapshots[name] as? JSONObject else {
+                        continue
+                    }
+                    fillListDefaults(&snapshot)
+                    snapshots[name] = snapshot
+                }
+                entries[index]["snapshots"] = snapshots
+            }
+        }
+        json[stackKey] = entries
+    }
+}
+
+// Writes the file's contents at the given version next to it.
+func backupTodoFile(at filePath: String, version: Int, contents: Data) throws -> String {
+    let formatter = DateFormatter()
+    formatter.dateFormat = "yyyyMMdd-HHmmss"
+    formatter.timeZone = TimeZone(abbreviation: "UTC")
+    let timestamp = formatter.string(from: Date())
+    
+    let backupPath = "\(filePath).v\(version)-backup-\(timestamp)"
+    try contents.write(to: URL(fileURLWithPath: backupPath), options: .withoutOverwriting)
+    return backupPath
+}
+
+func runMigrateCommand(_ arguments: [String], paths: StoragePaths) throws {
+    let usage = "jdi migrate [--dry-run]"
+    guard arguments.count <= 1 else {
+        throw JDIError.usage("Too many arguments for 'migrate'", usage: usage)
+    }
+    if let argument = arguments.first, argument != "--dry-run" && argument != "-n" {
+        throw JDIError.usage("Unknown option '\(argument)'", usage: usage)
+    }
+    let dryRun = !arguments.isEmpty
+    let filePath = paths.dataFile
+    
+    guard FileManager.default.fileExists(atPath: filePath) else {
+        print("No data file at \(filePath) yet. It will be created at schema version \(currentSchemaVersion)")
+        return
+    }
+    
+    var json: JSONObject
+    do {
+        json = try parseStoreJSON(Data(contentsOf: URL(fileURLWithPath: filePath)))
+    } catch {
+        throw JDIError.storage("Could not read \(filePath): \(error)")
+    }
+    
+    let version = schemaVersion(of: json)
+    try checkSchemaVersion(version, of: filePath)
+    guard version < currentSchemaVersion else {
+        print("\(filePath) is already at schema version \(currentSchemaVersion)")
+        return
+    }
+    
+    let applied = migrateStoreJSON(&json)
+    print("\(filePath) is at schema version \(version). \(dryRun ? "Would run" : "Running") \(applied.count) migration(s):")
+    for step in applied {
+        print("  → v\(step.migration.version): \(step.migration.summary)")
+        for change in step.changes {
+            print("      \(change)")
+        }
+    }
+    
+    if dryRun {
+        print("Dry run, nothing was changed")
+    } else {
+        _ = try loadTodoStore(paths)
+    }
+}
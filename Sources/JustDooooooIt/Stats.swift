@@ -0,0 +1,338 @@
+import Foundation
+
+// MARK: - Statistics
+// Everything here is worked out from CompletedTask.completedAt. Timestamps
+// are stored in UTC but grouped by local calendar day, the same way due
+// dates are, so a task finished late in the evening counts for that evening.
+
+enum StatsPeriod: CaseIterable {
+    case day
+    case week
+    case month
+    
+    var component: Calendar.Component {
+        switch self {
+        case .day: return .day
+        case .week: return .weekOfYear
+        case .month: return .month
+        }
+    }
+    
+    // How many periods are shown when no --since is given.
+    var defaultCount: Int {
+        switch self {
+        case .day: return 14
+        case .week: return 8
+        case .month: return 6
+        }
+    }
+    
+    var title: String {
+        switch self {
+        case .day: return "Per day"
+        case .week: return "Per week (starting Monday)"
+        case .month: return "Per month"
+        }
+    }
+}
+
+struct StatsBucket: Encodable {
+    let start: String
+    let count: Int
+}
+
+struct CompletionStats {
+    static let weekdayNames = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+    static let heatmapWeeks = 12
+    // Longer ranges are still covered by the weekly and monthly charts.
+    static let maxDayBars = 31
+    static let maxHeatmapColumns = 52
+    
+    let since: Date?
+    let until: Date
+    let tasks: [CompletedTask]
+    let countsByDay: [Date: Int]
+    private let calendar: Calendar
+    
+    init(history: [CompletedTask], since: Date? = nil, until: Date? = nil) {
+        var calendar = Calendar.current
+        calendar.firstWeekday = 2
+        self.calendar = calendar
+        let firstDay = since.map { calendar.startOfDay(for: $0) }
+        let lastDay = calendar.startOfDay(for: until ?? Date())
+        self.since = firstDay
+        self.until = lastDay
+        
+        let formatter = DateFormatter()
+        formatter.dateFormat = "yyyy-MM-dd HH:mm:ss"
+        formatter.locale = Locale(identifier: "en_US_POSIX")
+        formatter.timeZone = TimeZone(abbreviation: "UTC")
+        
+        var tasks: [CompletedTask] = []
+        var countsByDay: [Date: Int] = [:]
+        for task in history {
+            guard let completedAt = formatter.date(from: task.completedAt) else {
+                continue
+            }
+            
+            let day = calendar.startOfDay(for: completedAt)
+            if let firstDay = firstDay, day < firstDay {
+                continue
+            }
+            if day > lastDay {
+                continue
+            }
+            
+            tasks.append(task)
+            countsByDay[day, default: 0] += 1
+        }
+        self.tasks = tasks
+        self.countsByDay = countsByDay
+    }
+    
+    var isFiltered: Bool {
+        return since != nil || !calendar.isDateInToday(until)
+    }
+    
+    // MARK: Buckets
+    func buckets(_ period: StatsPeriod) -> [StatsBucket] {
+        let formatter = dueDateFormatter()
+        let earliest = since ?? calendar.date(byAdding: period.component, value: 1 - period.defaultCount, to: until) ?? until
+        guard var start = calendar.dateInterval(of: period.component, for: earliest)?.start else {
+            return []
+        }
+        
+        var buckets: [StatsBucket] = []
+        while start <= until {
+            guard let next = calendar.date(byAdding: period.component, value: 1, to: start) else {
+                break
+            }
+            let count = countsByDay.filter { $0.key >= start && $0.key < next }.values.reduce(0, +)
+            buckets.append(StatsBucket(start: formatter.string(from: start), count: count))
+            start = next
+        }
+        return buckets
+    }
+    
+    // MARK: Streaks
+    // Consecutive days with at least one completion, ending at --until
+    // (today by default). A streak stays alive until a whole day passes
+    // without a completion, so it is not reset first thing in the morning.
+    var currentStreak: Int {
+        var day = until
+        if countsByDay[day] == nil, let yesterday = calendar.date(byAdding: .day, value: -1, to: day) {
+            day = yesterday
+        }
+        
+        var streak = 0
+        while countsByDay[day] != nil {
+            streak += 1
+            guard let previous = calendar.date(byAdding: .day, value: -1, to: day) else {
+                break
+            }
+            day = previous
+        }
+        return streak
+    }
+    
+    var longestStreak: Int {
+        var longest = 0
+        var streak = 0
+        var previous: Date?
+        
+        for day in countsByDay.keys.sorted() {
+            if let previous = previous, calendar.date(byAdding: .day, value: 1, to: previous) == day {
+                streak += 1
+            } else {
+                streak = 1
+            }
+            longest = max(longest, streak)
+            previous = day
+        }
+        return longest
+    }
+    
+    // MARK: Weekdays
+    // Week starts (Mondays) covered by the heatmap: the whole range when
+    // --since is given, otherwise the last few weeks.
+    var heatmapWeekStarts: [Date] {
+        let earliest = since ?? calendar.date(byAdding: .weekOfYear, value: 1 - CompletionStats.heatmapWeeks, to: until) ?? until
+        guard var start = calendar.dateInterval(of: .weekOfYear, for: earliest)?.start else {
+            return []
+        }
+        
+        var weekStarts: [Date] = []
+        while start <= until {
+            weekStarts.append(start)
+            guard let next = calendar.date(byAdding: .weekOfYear, value: 1, to: start) else {
+                break
+            }
+            start = next
+        }
+        return weekStarts
+    }
+    
+    // The heatmap's cells as [weekday][week], Monday first. Days outside
+    // the range are nil.
+    func heatmap() -> [[Int?]] {
+        let weekStarts = heatmapWeekStarts
+        return (0..<7).map { weekday in
+            weekStarts.map { weekStart -> Int? in
+                guard let day = calendar.date(byAdding: .day, value: weekday, to: weekStart), day <= until else {
+                    return nil
+                }
+                if let since = since, day < since {
+                    return nil
+                }
+                return countsByDay[day] ?? 0
+            }
+        }
+    }
+    
+    func weekdayTotals() -> [Int] {
+        return heatmap().map { row in row.compactMap { $0 }.reduce(0, +) }
+    }
+    
+    // MARK: Display
+    func display(completedCount: UInt32) {
+        let formatter = dueDateFormatter()
+        print("📊 Completion Statistics:")
+        print("Total completed: \(completedCount) tasks")
+        if isFiltered {
+            let from = since.map { formatter.string(from: $0) } ?? "the beginning"
+            print("From \(from) to \(formatter.string(from: until)): \(tasks.count) tasks")
+        }
+        print("")
+        
+        if tasks.isEmpty {
+            print(isFiltered ? "No tasks completed in this range." : "No completed tasks yet.")
+            return
+        }
+        
+        print("🔥 Current streak: \(dayCount(currentStreak)) · Longest: \(dayCount(longestStreak))\n")
+        
+        for period in StatsPeriod.allCases {
+            var periodBuckets = buckets(period)
+            if period == .day && periodBuckets.count > CompletionStats.maxDayBars {
+                periodBuckets = Array(periodBuckets.suffix(CompletionStats.maxDayBars))
+                print("\(period.title) (last \(CompletionStats.maxDayBars) days):")
+            } else {
+                print("\(period.title):")
+            }
+            for line in barChart(periodBuckets, labelLength: period == .month ? 7 : 10) {
+                print("  \(line)")
+            }
+            print("")
+        }
+        
+        // Totals cover the whole range; the grid only its last year.
+        let heatmap = self.heatmap()
+        let isTrimmed = heatmap.first.map { $0.count > CompletionStats.maxHeatmapColumns } ?? false
+        print(isTrimmed ? "By weekday (grid shows the last \(CompletionStats.maxHeatmapColumns) weeks):" : "By weekday:")
+        let shownWeeks = heatmap.map { $0.suffix(CompletionStats.maxHeatmapColumns) }
+        let busiestDay = shownWeeks.flatMap { $0.compactMap { $0 } }.max() ?? 0
+        for (weekday, row) in heatmap.enumerated() {
+            let cells = shownWeeks[weekday].map { $0.map { heatmapShade($0, busiest: busiestDay) } ?? " " }.joined()
+            let total = row.compactMap { $0 }.reduce(0, +)
+            print("  \(CompletionStats.weekdayNames[weekday]) \(cells) \(total)")
+        }
+        print("      less · ░ ▒ ▓ █ more\n")
+        
+        print("Recently completed:")
+        for task in tasks.suffix(10).reversed() {
+            let subtaskInfo = task.hadSubtasks ? " (\(task.subtaskCount) subtasks)" : ""
+            print("[\(task.id)] ✓ \(task.text)\(subtaskInfo) (\(task.completedAt))")
+        }
+    }
+    
+    private func dayCount(_ days: Int) -> String {
+        return days == 1 ? "1 day" : "\(days) days"
+    }
+    
+    private func barChart(_ buckets: [StatsBucket], labelLength: Int, width: Int = 30) -> [String] {
+        let largest = buckets.map { $0.count }.max() ?? 0
+        return buckets.map { bucket -> String in
+            // Any completion gets at least one block so it does not read as zero.
+            let length = largest == 0 || bucket.count == 0 ? 0 : max(1, bucket.count * width / largest)
+            let label = String(bucket.start.prefix(labelLength))
+            return "\(label) \(String(repeating: "█", count: length)) \(bucket.count)"
+        }
+    }
+    
+    private func heatmapShade(_ count: Int, busiest: Int) -> String {
+        guard count > 0, busiest > 0 else {
+            return "·"
+        }
+        let shades = ["░", "▒", "▓", "█"]
+        let level = (count * shades.count + busiest - 1) / busiest
+        return shades[min(max(level, 1), shades.count) - 1]
+    }
+}
+
+// Besides the due date forms, accepts "yesterday" and offsets into the
+// past like -7d or -4w, which is what a --since usually wants.
+func parseStatsDate(_ input: String) -> Date? {
+    let formatter = dueDateFormatter()
+    let lowered = input.lowercased()
+    
+    var pastOffset: Int?
+    if lowered == "yesterday" {
+        pastOffset = 1
+    } else if lowered.hasPrefix("-"), let unit = lowered.last, let amount = Int(lowered.dropFirst().dropLast()) {
+        switch unit {
+        case "d":
+            pastOffset = amount
+        case "w":
+            pastOffset = amount * 7
+        default:
+            return nil
+        }
+    }
+    
+    if let pastOffset = pastOffset {
+        let calendar = Calendar.current
+        return calendar.date(byAdding: .day, value: -pastOffset, to: calendar.startOfDay(for: Date()))
+    }
+    return parseDueDate(input).flatMap { formatter.date(from: $0) }
+}
+
+func parseStatsOptions(_ arguments: ArraySlice<String>) throws -> (since: Date?, until: Date?) {
+    let usage = "jdi stats [--since <date>] [--until <date>]"
+    var since: Date?
+    var until: Date?
+    var index = arguments.startIndex
+    
+    while index < arguments.endIndex {
+        var argument = arguments[index]
+        var value: String?
+        if let separator = argument.firstIndex(of: "=") {
+            value = String(argument[argument.index(after: separator)...])
+            argument = String(argument[..<separator])
+        } else if index + 1 < arguments.endIndex {
+            value = arguments[index + 1]
+            index += 1
+        }
+        index += 1
+        
+        guard argument == "--since" || argument == "--until" else {
+            throw JDIError.usage("Unknown option '\(argument)'", usage: usage)
+        }
+        guard let dateValue = value else {
+            throw JDIError.usage("'\(argument)' requires a date", usage: usage)
+        }
+        guard let date = parseStatsDate(dateValue) else {
+            throw JDIError.usage("Invalid date '\(dateValue)'. Use YYYY-MM-DD, today, yesterday, -7d or -4w", usage: usage)
+        }
+        
+        if argument == "--since" {
+            since = date
+        } else {
+            until = date
+        }
+    }
+    
+    if let since = since, let until = until, since > until {
+        throw JDIError.usage("--since must not be after --until", usage: usage)
+    }
+    return (since, until)
+}
@@ -0,0 +1,550 @@
+import Foundation
+#if canImport(Glibc)
+import Glibc
+#elseif canImport(Darwin)
+import Darwin
+#endif
+
+// MARK: - Terminal
+enum Key {
+    case up
+    case down
+    case left
+    case right
+    case enter
+    case escape
+    case backspace
+    case tab
+    case interrupt
+    case character(Character)
+    case unknown
+}
+
+// Puts the terminal into raw mode on an alternate screen and reads keys.
+// Reads time out every 100ms so a lone Escape can be told apart from the
+// start of an arrow key sequence.
+final class RawTerminal {
+    private var original = termios()
+    
+    init() throws {
+        guard isatty(STDIN_FILENO) == 1, isatty(STDOUT_FILENO) == 1 else {
+            throw JDIError.usage("'tui' needs an interactive terminal")
+        }
+        
+        tcgetattr(STDIN_FILENO, &original)
+        var raw = original
+        raw.c_lflag &= ~tcflag_t(ECHO | ICANON | ISIG | IEXTEN)
+        raw.c_iflag &= ~tcflag_t(IXON | ICRNL)
+        withUnsafeMutableBytes(of: &raw.c_cc) { controlCharacters in
+            controlCharacters[Int(VMIN)] = 0
+            controlCharacters[Int(VTIME)] = 1
+        }
+        tcsetattr(STDIN_FILENO, TCSAFLUSH, &raw)
+        
+        output("\u{1B}[?1049h\u{1B}[?25l")
+    }
+    
+    func restore() {
+        output("\u{1B}[?25h\u{1B}[?1049l")
+        tcsetattr(STDIN_FILENO, TCSAFLUSH, &original)
+    }
+    
+    func output(_ text: String) {
+        fputs(text, stdout)
+        fflush(stdout)
+    }
+    
+    var size: (rows: Int, columns: Int) {
+        var windowSize = winsize()
+        if ioctl(STDOUT_FILENO, UInt(TIOCGWINSZ), &windowSize) == 0, windowSize.ws_row > 0, windowSize.ws_col > 0 {
+            return (Int(windowSize.ws_row), Int(windowSize.ws_col))
+        }
+        return (24, 80)
+    }
+    
+    private func readByte() -> UInt8? {
+        var byte: UInt8 = 0
+        return read(STDIN_FILENO, &byte, 1) == 1 ? byte : nil
+    }
+    
+    // A timed-out read and one at the end of input both return no bytes;
+    // only a closed input reports a hangup or error.
+    private var isInputClosed: Bool {
+        var descriptor = pollfd(fd: STDIN_FILENO, events: Int16(POLLIN), revents: 0)
+        guard poll(&descriptor, 1, 0) >= 0 else {
+            return errno != EINTR
+        }
+        return descriptor.revents & Int16(POLLHUP | POLLERR | POLLNVAL) != 0
+    }
+    
+    // Blocks until a key is pressed. Returns nil once the terminal has gone
+    // away, since reads then return nothing straight away instead of
+    // waiting for the timeout.
+    func readKey() -> Key? {
+        var pressed: UInt8?
+        while pressed == nil {
+            pressed = readByte()
+            if pressed == nil && isInputClosed {
+                return nil
+            }
+        }
+        guard let byte = pressed else {
+            return .unknown
+        }
+        
+        switch byte {
+        case 27:
+            guard let next = readByte(), next == UInt8(ascii: "[") || next == UInt8(ascii: "O"),
+                  let code = readByte() else {
+                return .escape
+            }
+            
+            switch code {
+            case UInt8(ascii: "A"): return .up
+            case UInt8(ascii: "B"): return .down
+            case UInt8(ascii: "C"): return .right
+            case UInt8(ascii: "D"): return .left
+            case UInt8(ascii: "0")...UInt8(ascii: "9"):
+                // Sequences such as Delete ("\e[3~") end with a tilde.
+                while let trailing = readByte(), trailing != UInt8(ascii: "~") {}
+                return .unknown
+            default:
+                return .unknown
+            }
+        case 13, 10:
+            return .enter
+        case 127, 8:
+            return .backspace
+        case 9:
+            return .tab
+        case 3:
+            return .interrupt
+        case 0..<32:
+            return .unknown
+        default:
+            var bytes = [byte]
+            let continuationCount = byte >= 0xF0 ? 3 : byte >= 0xE0 ? 2 : byte >= 0xC0 ? 1 : 0
+            for _ in 0..<continuationCount {
+                if let continuation = readByte() {
+                    bytes.append(continuation)
+                }
+            }
+            return String(decoding: bytes, as: UTF8.self).first.map { .character($0) } ?? .unknown
+        }
+    }
+}
+
+// MARK: - TUI
+// A keyboard-driven view of one list. It holds no lock while idle: every
+// change is one locked load/modify/save cycle through `mutateList`, so
+// edits made from other terminals in the meantime are kept.
+final class TodoTUI {
+    private enum Mode {
+        case browse
+        case input(prompt: String, text: String, submit: (String) -> Void)
+        case confirm(prompt: String, action: () -> Void)
+    }
+    
+    private static let helpLine = "j/k move  h/l fold  a add  s sub  e edit  d done  D delete  u undo  r reload  q quit"
+    
+    private let paths: StoragePaths
+    private let listName: String
+    private var todoList = TodoList()
+    private var collapsed = Set<UInt32>()
+    private var selectedId: UInt32?
+    private var scrollOffset = 0
+    private var mode = Mode.browse
+    private var message = ""
+    private var isRunning = true
+    
+    init(paths: StoragePaths, listName: String) {
+        self.paths = paths
+        self.listName = listName
+    }
+    
+    func run() throws {
+        try reload()
+        
+        let terminal = try RawTerminal()
+        defer { terminal.restore() }
+        
+        while isRunning {
+            render(on: terminal)
+            guard let key = terminal.readKey() else {
+                break
+            }
+            handle(key)
+        }
+    }
+    
+    // MARK: Rows
+    private func visibleRows() -> [(item: TodoItem, depth: Int)] {
+        var rows: [(item: TodoItem, depth: Int)] = []
+        
+        func visit(_ item: TodoItem, depth: Int) {
+            rows.append((item, depth))
+            guard !collapsed.contains(item.id) else {
+                return
+            }
+            for child in todoList.getSubItems(parentId: item.id) {
+                visit(child, depth: depth + 1)
+            }
+        }
+        
+        for rootItem in todoList.getRootItems() {
+            visit(rootItem, depth: 0)
+        }
+        return rows
+    }
+    
+    private var selectedItem: TodoItem? {
+        return selectedId.flatMap { todoList.items[String($0)] }
+    }
+    
+    private func hasChildren(_ id: UInt32) -> Bool {
+        return todoList.items.values.contains { $0.parentId == id }
+    }
+    
+    // Keeps the selection on a visible row, falling back to the row that
+    // took the old one's place.
+    private func fixSelection(preferredIndex: Int = 0) {
+        let rows = visibleRows()
+        if let selectedId = selectedId, rows.contains(where: { $0.item.id == selectedId }) {
+            return
+        }
+        selectedId = rows.isEmpty ? nil : rows[min(max(preferredIndex, 0), rows.count - 1)].item.id
+    }
+    
+    private func moveSelection(by offset: Int) {
+        let rows = visibleRows()
+        guard !rows.isEmpty else {
+            return
+        }
+        
+        let index = rows.firstIndex { $0.item.id == selectedId } ?? 0
+        selectedId = rows[min(max(index + offset, 0), rows.count - 1)].item.id
+    }
+    
+    // MARK: Rendering
+    private func render(on terminal: RawTerminal) {
+        let size = terminal.size
+        let rows = visibleRows()
+        let listHeight = max(size.rows - 3, 1)
+        let selectedIndex = rows.firstIndex { $0.item.id == selectedId } ?? 0
+        
+        if selectedIndex < scrollOffset {
+            scrollOffset = selectedIndex
+        } else if selectedIndex >= scrollOffset + listHeight {
+            scrollOffset = selectedIndex - listHeight + 1
+        }
+        
+        var frame = "\u{1B}[H\u{1B}[2J"
+        frame += "\u{1B}[1m" + fit("jdi · \(listName) · \(todoList.items.count) open", to: size.columns) + "\u{1B}[0m\n"
+        
+        if rows.isEmpty {
+            frame += fit("  No todos yet. Press 'a' to add one.", to: size.columns) + "\n"
+        }
+        
+        let today = todayDateString()
+        for (index, row) in rows.enumerated().dropFirst(scrollOffset).prefix(listHeight) {
+            let marker: String
+            if hasChildren(row.item.id) {
+                marker = collapsed.contains(row.item.id) ? "▸" : "▾"
+            } else {
+                marker = "○"
+            }
+            
+            let indent = String(repeating: "  ", count: row.depth)
+            let line = fit("\(indent)\(marker) [\(row.item.id)] \(row.item.summary(today: today))", to: size.columns)
+            if index == selectedIndex {
+                frame += "\u{1B}[7m" + line.padding(toLength: size.columns, withPad: " ", startingAt: 0) + "\u{1B}[0m\n"
+            } else {
+                frame += line + "\n"
+            }
+        }
+        
+        frame += "\u{1B}[\(size.rows - 1);1H" + fit(message, to: size.columns)
+        frame += "\u{1B}[\(size.rows);1H"
+        switch mode {
+        case .browse:
+            frame += "\u{1B}[2m" + fit(TodoTUI.helpLine, to: size.columns) + "\u{1B}[0m"
+        case .input(let prompt, let text, _):
+            frame += fit("\(prompt): \(text)▏", to: size.columns)
+        case .confirm(let prompt, _):
+            frame += fit("\(prompt) (y/n)", to: size.columns)
+        }
+        
+        terminal.output(frame)
+    }
+    
+    // Cuts text to the given number of terminal columns rather than
+    // characters, as wide characters and emoji take up two.
+    private func fit(_ text: String, to width: Int) -> String {
+        var fitted = ""
+        var used = 0
+        for character in text {
+            let columns = TodoTUI.columns(of: character)
+            guard used + columns <= width else {
+                break
+            }
+            fitted.append(character)
+            used += columns
+        }
+        return fitted
+    }
+    
+    static func columns(of character: Character) -> Int {
+        guard let scalar = character.unicodeScalars.first else {
+            return 0
+        }
+        // Emoji shown as pictures, including text symbols with U+FE0F.
+        if scalar.properties.isEmojiPresentation || character.unicodeScalars.contains(where: { $0.value == 0xFE0F }) {
+            return 2
+        }
+        
+        switch scalar.value {
+        case 0x1100...0x115F, 0x2E80...0x303E, 0x3041...0xA4CF, 0xAC00...0xD7A3, 0xF900...0xFAFF,
+             0xFE30...0xFE4F, 0xFF00...0xFF60, 0xFFE0...0xFFE6, 0x20000...0x3FFFD:
+            // East Asian wide and fullwidth ranges.
+            return 2
+        default:
+            return 1
+        }
+    }
+    
+    // MARK: Input
+    private func handle(_ key: Key) {
+        switch mode {
+        case .browse:
+            handleBrowse(key)
+            
+        case .input(let prompt, var text, let submit):
+            switch key {
+            case .enter:
+                mode = .browse
+                let trimmed = text.trimmingCharacters(in: .whitespaces)
+                if trimmed.isEmpty {
+                    message = "Nothing entered"
+                } else {
+                    submit(trimmed)
+                }
+            case .escape, .interrupt:
+                mode = .browse
+                message = "Cancelled"
+            case .backspace:
+                if !text.isEmpty {
+                    text.removeLast()
+                }
+                mode = .input(prompt: prompt, text: text, submit: submit)
+            case .character(let character):
+                text.append(character)
+                mode = .input(prompt: prompt, text: text, submit: submit)
+            default:
+                break
+            }
+            
+        case .confirm(_, let action):
+            mode = .browse
+            if case .character("y") = key {
+                action()
+            } else {
+                message = "Cancelled"
+            }
+        }
+    }
+    
+    private func handleBrowse(_ key: Key) {
+        message = ""
+        
+        switch key {
+        case .character("q"), .escape, .interrupt:
+            isRunning = false
+        case .down, .character("j"):
+            moveSelection(by: 1)
+        case .up, .character("k"):
+            moveSelection(by: -1)
+        case .character("g"):
+            selectedId = visibleRows().first?.item.id
+        case .character("G"):
+            selectedId = visibleRows().last?.item.id
+        case .right, .character("l"):
+            expandSelection()
+        case .left, .character("h"):
+            collapseSelection()
+        case .tab, .character(" "):
+            if let item = selectedItem, hasChildren(item.id) {
+                if collapsed.remove(item.id) == nil {
+                    collapsed.insert(item.id)
+                }
+            }
+        case .character("a"):
+            mode = .input(prompt: "Add todo", text: "", submit: { text in self.addSibling(text: text) })
+        case .character("s"), .character("A"):
+            guard let item = selectedItem else {
+                message = "Select a todo to add a subtask to"
+                return
+            }
+            mode = .input(prompt: "Add subtask to [\(item.id)]", text: "", submit: { text in self.addSubtask(to: item, text: text) })
+        case .character("e"), .enter:
+            guard let item = selectedItem else {
+                return
+            }
+            mode = .input(prompt: "Edit [\(item.id)]", text: item.text, submit: { text in self.edit(item, text: text) })
+        case .character("d"), .character("x"):
+            completeSelection()
+        case .character("D"):
+            guard let item = selectedItem else {
+                return
+            }
+            let subtaskCount = todoList.subtree(of: item.id).count - 1
+            let subtaskInfo = subtaskCount > 0 ? " and \(subtaskCount) subtask(s)" : ""
+            mode = .confirm(prompt: "Delete [\(item.id)]\(subtaskInfo)?", action: { self.delete(item) })
+        case .character("u"):
+            undo()
+        case .character("r"):
+            do {
+                try reload()
+                message = "Reloaded"
+            } catch {
+                message = "Error: \(error)"
+            }
+        default:
+            break
+        }
+    }
+    
+    private func expandSelection() {
+        guard let item = selectedItem, hasChildren(item.id) else {
+            return
+        }
+        
+        if collapsed.remove(item.id) == nil {
+            selectedId = todoList.getSubItems(parentId: item.id).first?.id
+        }
+    }
+    
+    private func collapseSelection() {
+        guard let item = selectedItem else {
+            return
+        }
+        
+        if hasChildren(item.id) && !collapsed.contains(item.id) {
+            collapsed.insert(item.id)
+        } else if let parentId = item.parentId {
+            selectedId = parentId
+        }
+    }
+    
+    // MARK: Changes
+    private func reload() throws {
+        let lock = try FileLock.acquire(path: paths.lockFile)
+        defer { lock.release() }
+        
+        todoList = try loadTodoStore(paths).lists[listName] ?? TodoList()
+        fixSelection()
+    }
+    
+    private func perform(_ action: String, _ body: (inout TodoList) throws -> Void) {
+        do {
+            todoList = try mutateList(listName, paths: paths, action: action, body)
+        } catch {
+            message = "Error: \(error)"
+        }
+    }
+    
+    private func addSibling(text: String) {
+        let sibling = selectedItem
+        var newId: UInt32?
+        perform("add: \(text)") { todoList in
+            let id = todoList.addItem(text: text, parentId: sibling?.parentId)
+            // Place it straight after the selected todo when they sort together.
+            if let sibling = sibling, sibling.priority == nil, let index = todoList.orderIndex(id: sibling.id) {
+                _ = todoList.reorderItem(id: id, toIndex: index + 1)
+            }
+            newId = id
+        }
+        
+        if let newId = newId {
+            selectedId = newId
+            message = "Added todo \(newId)"
+        }
+    }
+    
+    private func addSubtask(to parent: TodoItem, text: String) {
+        var newId: UInt32?
+        perform("sub: \(text)") { todoList in
+            guard todoList.items[String(parent.id)] != nil else {
+                throw JDIError.notFound("Parent todo \(parent.id) does not exist")
+            }
+            newId = todoList.addItem(text: text, parentId: parent.id)
+        }
+        
+        if let newId = newId {
+            collapsed.remove(parent.id)
+            selectedId = newId
+            message = "Added subtask \(newId) to [\(parent.id)]"
+        }
+    }
+    
+    private func edit(_ item: TodoItem, text: String) {
+        guard text != item.text else {
+            message = "No changes"
+            return
+        }
+        
+        perform("edit \(item.id): \(text)") { todoList in
+            guard todoList.editItem(id: item.id, text: text) else {
+                throw JDIError.notFound("Todo \(item.id) not found")
+            }
+            message = "Updated todo \(item.id)"
+        }
+    }
+    
+    private func completeSelection() {
+        guard let item = selectedItem else {
+            return
+        }
+        
+        let index = visibleRows().firstIndex { $0.item.id == item.id } ?? 0
+        perform("done \(item.id): \(item.text)") { todoList in
+            let result = todoList.completeItem(id: item.id)
+            guard result.success else {
+                throw JDIError.notFound("Todo \(item.id) not found")
+            }
+            
+            let subtaskInfo = result.subtaskCount > 0 ? " and \(result.subtaskCount) subtask(s)" : ""
+            let nextInfo = result.nextOccurrenceId.map { ", next occurrence is [\($0)]" } ?? ""
+            message = "✓ Completed todo \(item.id)\(subtaskInfo)\(nextInfo)"
+        }
+        fixSelection(preferredIndex: index)
+    }
+    
+    private func delete(_ item: TodoItem) {
+        let index = visibleRows().firstIndex { $0.item.id == item.id } ?? 0
+        perform("delete \(item.id)") { todoList in
+            guard todoList.trashItem(id: item.id) else {
+                throw JDIError.notFound("Todo \(item.id) not found")
+            }
+            message = "Moved todo \(item.id) to the trash"
+        }
+        fixSelection(preferredIndex: index)
+    }
+    
+    private func undo() {
+        do {
+            let lock = try FileLock.acquire(path: paths.lockFile)
+            defer { lock.release() }
+            
+            var store = try loadTodoStore(paths)
+            if let entry = try stepJournal(&store, paths: paths, redo: false) {
+                message = "↶ Undid: \(entry.action)"
+            } else {
+                message = "Nothing to undo"
+            }
+            todoList = store.lists[listName] ?? TodoList()
+        } catch {
+            message = "Error: \(error)"
+        }
+        fixSelection()
+    }
+}
@@ -0,0 +1,256 @@
+import Foundation
+
+// MARK: - todo.txt
+// Maps todos onto the todo.txt format (https://github.com/todotxt/todo.txt).
+// #tags become +projects and @tags stay @contexts. Fields todo.txt has no
+// slot for are written as key:value extensions so nothing is lost:
+//   id:        the todo's ID, referenced by parent:
+//   parent:    the parent todo's ID, also kept for completed todos
+//   pos:       manual sort position among siblings
+//   due:       due date (a widely used todo.txt extension)
+//   rec:       repeat rule, e.g. rec:weekly:mon,thu or rec:every:3d
+//   pri:       priority of a completed todo, whose line cannot start with (A)
+//   created:   full creation timestamp, the plain date only has the day
+//   completed: full completion timestamp
+//   subtasks:  number of subtasks archived with a completed todo
+//   archived:  marks a subtask archived with the completed todo above it;
+//              the value is its parent's ID within that archive
+//
+// Words are separated by single spaces; runs of spaces and tabs, as other
+// tools sometimes leave, are read as one. Words in the text that todo.txt
+// would read as something else, such as a literal +word or a due:... that
+// is not a field, are written with a leading backslash that import removes.
+// That escape is a jdi extension: other todo.txt tools show the backslash.
+
+let todoTxtKeys: Set<String> = ["id", "parent", "pos", "due", "rec", "pri", "created", "completed", "subtasks", "archived"]
+
+func exportTodoTxt(_ todoList: TodoList) -> String {
+    var lines: [String] = []
+    
+    for item in todoList.orderedItems() {
+        var fields: [String] = []
+        if let priority = item.priority {
+            fields.append("(\(todoTxtPriority(priority)))")
+        }
+        fields.append(String(item.createdAt.prefix(10)))
+        fields.append(todoTxtText(fromTodoText: item.text, tags: item.tags))
+        if let dueDate = item.dueDate {
+            fields.append("due:\(dueDate)")
+        }
+        fields.append("id:\(item.id)")
+        if let parentId = item.parentId {
+            fields.append("parent:\(parentId)")
+        }
+        fields.append("pos:\(item.position)")
+        if let recurrence = item.recurrence {
+            fields.append("rec:\(recurrence.rawValue)")
+        }
+        fields.append("created:\(todoTxtTimestamp(item.createdAt))")
+        lines.append(fields.joined(separator: " "))
+    }
+    
+    for task in todoList.completedHistory {
+        var fields = ["x", String(task.completedAt.prefix(10))]
+        if let createdAt = task.createdAt {
+            fields.append(String(createdAt.prefix(10)))
+        }
+        fields.append(todoTxtText(fromTodoText: task.text, tags: TodoItem.extractTags(from: task.text)))
+        fields += todoTxtArchivedFields(dueDate: task.dueDate, priority: task.priority, recurrence: task.recurrence)
+        fields.append("id:\(task.id)")
+        if let parentId = task.parentId {
+            fields.append("parent:\(parentId)")
+        }
+        if let position = task.position {
+            fields.append("pos:\(position)")
+        }
+        if task.hadSubtasks {
+            fields.append("subtasks:\(task.subtaskCount)")
+        }
+        if let createdAt = task.createdAt {
+            fields.append("created:\(todoTxtTimestamp(createdAt))")
+        }
+        fields.append("completed:\(todoTxtTimestamp(task.completedAt))")
+        lines.append(fields.joined(separator: " "))
+        
+        // Archived subtasks follow their completed todo, parents first.
+        for subtask in task.subtasks {
+            var subtaskFields = ["x", String(task.completedAt.prefix(10)), String(subtask.createdAt.prefix(10))]
+            subtaskFields.append(todoTxtText(fromTodoText: subtask.text, tags: TodoItem.extractTags(from: subtask.text)))
+            subtaskFields += todoTxtArchivedFields(dueDate: subtask.dueDate, priority: subtask.priority, recurrence: subtask.recurrence)
+            subtaskFields.append("id:\(subtask.id)")
+            subtaskFields.append("archived:\(subtask.parentId)")
+            if let position = subtask.position {
+                subtaskFields.append("pos:\(position)")
+            }
+            subtaskFields.append("created:\(todoTxtTimestamp(subtask.createdAt))")
+            lines.append(subtaskFields.joined(separator: " "))
+        }
+    }
+    
+    return lines.map { $0 + "\n" }.joined()
+}
+
+// Due date, priority and repeat rule of a completed todo or archived
+// subtask, so reopening it after an import brings them back.
+private func todoTxtArchivedFields(dueDate: String?, priority: Priority?, recurrence: Recurrence?) -> [String] {
+    var fields: [String] = []
+    if let dueDate = dueDate {
+        fields.append("due:\(dueDate)")
+    }
+    if let priority = priority {
+        fields.append("pri:\(todoTxtPriority(priority))")
+    }
+    if let recurrence = recurrence {
+        fields.append("rec:\(recurrence.rawValue)")
+    }
+    return fields
+}
+
+func parseTodoTxt(_ content: String) -> [ImportedTask] {
+    var tasks: [ImportedTask] = []
+    // The completed task that archived: lines belong to.
+    var archiveIndex: Int?
+    
+    for (lineNumber, line) in content.split(whereSeparator: { $0.isNewline }).enumerated() {
+        var tokens = line.split(whereSeparator: { $0 == " " || $0 == "\t" }).map(String.init)
+        
+        var isCompleted = false
+        var completionDate: String?
+        var creationDate: String?
+        var priority: Priority?
+        
+        if tokens.first == "x" {
+            isCompleted = true
+            tokens.removeFirst()
+            if let first = tokens.first, isTodoTxtDate(first) {
+                completionDate = first
+                tokens.removeFirst()
+            }
+        } else if let first = tokens.first, first.count == 3, first.hasPrefix("("), first.hasSuffix(")") {
+            priority = todoTxtPriority(fromLetter: String(first.dropFirst().prefix(1)))
+            tokens.removeFirst()
+        }
+        
+        if let first = tokens.first, isTodoTxtDate(first) {
+            creationDate = first
+            tokens.removeFirst()
+        }
+        
+        var fields: [String: String] = [:]
+        var words: [String] = []
+        for token in tokens {
+            if token.hasPrefix("\\") {
+                words.append(String(token.dropFirst()))
+            } else if let key = todoTxtKey(of: token) {
+                fields[key] = String(token.dropFirst(key.count + 1))
+            } else if token.hasPrefix("+") && token.count > 1 {
+                words.append("#" + String(token.dropFirst()))
+            } else {
+                words.append(token)
+            }
+        }
+        
+        let text = words.joined(separator: " ")
+        guard !text.trimmingCharacters(in: .whitespaces).isEmpty else {
+            continue
+        }
+        
+        var task = ImportedTask(sourceId: fields["id"] ?? "line-\(lineNumber + 1)", text: text)
+        task.parentSourceId = fields["parent"]
+        task.priority = priority ?? fields["pri"].flatMap(todoTxtPriority(fromLetter:))
+        task.dueDate = fields["due"]
+        task.position = fields["pos"].flatMap { Int($0) }
+        task.recurrence = fields["rec"].flatMap { Recurrence(rawValue: $0) }
+        task.createdAt = fields["created"].map(timestampFromTodoTxt) ?? creationDate.map { "\($0) 00:00:00" }
+        
+        if isCompleted, let archiveIndex = archiveIndex, let archivedParent = fields["archived"].flatMap({ UInt32($0) }) {
+            tasks[archiveIndex].archivedSubtasks.append(ArchivedSubtask(
+                id: fields["id"].flatMap { UInt32($0) } ?? 0,
+                text: text,
+                parentId: archivedParent,
+                createdAt: task.createdAt ?? currentTimestamp(),
+                dueDate: task.dueDate,
+                priority: task.priority,
+                position: task.position,
+                recurrence: task.recurrence
+            ))
+            continue
+        }
+        
+        if isCompleted {
+            task.completedAt = fields["completed"].map(timestampFromTodoTxt)
+                ?? completionDate.map { "\($0) 00:00:00" }
+                ?? currentTimestamp()
+            task.subtaskCount = fields["subtasks"].flatMap { Int($0) } ?? 0
+            archiveIndex = tasks.count
+        } else {
+            archiveIndex = nil
+        }
+        
+        tasks.append(task)
+    }
+    
+    return tasks
+}
+
+func todoTxtPriority(_ priority: Priority) -> String {
+    switch priority {
+    case .high: return "A"
+    case .medium: return "B"
+    case .low: return "C"
+    }
+}
+
+// Anything below C in todo.txt is folded into low.
+func todoTxtPriority(fromLetter letter: String) -> Priority? {
+    switch letter {
+    case "A": return .high
+    case "B": return .medium
+    case let other where other.count == 1 && other >= "C" && other <= "Z": return .low
+    default: return nil
+    }
+}
+
+// Only words that are among the todo's tags become +projects.
+func todoTxtText(fromTodoText text: String, tags: [String]) -> String {
+    let words = text.split(whereSeparator: { $0 == " " || $0 == "\t" }).map(String.init)
+    return words.enumerated().map { index, word -> String in
+        if word.hasPrefix("#"), let tag = TodoItem.extractTags(from: word).first, tags.contains(tag) {
+            return "+" + String(word.dropFirst())
+        }
+        return todoTxtNeedsEscape(word, isFirst: index == 0) ? "\\" + word : word
+    }.joined(separator: " ")
+}
+
+// Words import would not read back as plain text. At the very start of the
+// text, "x", "(A)" and dates would be taken for the line's prefix.
+func todoTxtNeedsEscape(_ word: String, isFirst: Bool) -> Bool {
+    if word.hasPrefix("\\") || (word.hasPrefix("+") && word.count > 1) || todoTxtKey(of: word) != nil {
+        return true
+    }
+    let isPriority = word.count == 3 && word.hasPrefix("(") && word.hasSuffix(")")
+    return isFirst && (word == "x" || isPriority || isTodoTxtDate(word))
+}
+
+// The field key of a key:value token, if it is one of ours.
+func todoTxtKey(of token: String) -> String? {
+    guard let separator = token.firstIndex(of: ":") else {
+        return nil
+    }
+    let key = String(token[..<separator])
+    return todoTxtKeys.contains(key) ? key : nil
+}
+
+func isTodoTxtDate(_ token: String) -> Bool {
+    return token.range(of: "^\\d{4}-\\d{2}-\\d{2}$", options: .regularExpression) != nil
+}
+
+// Timestamps are stored as "yyyy-MM-dd HH:mm:ss"; todo.txt values cannot
+// contain spaces, so they are written with a T separator instead.
+func todoTxtTimestamp(_ timestamp: String) -> String {
+    return timestamp.replacingOccurrences(of: " ", with: "T")
+}
+
+func timestampFromTodoTxt(_ value: String) -> String {
+    return value.replacingOccurrences(of: "T", with: " ")
+}
@@ -1,17 +1,311 @@
 import Foundation
+#if canImport(Glibc)
+import Glibc
+#elseif canImport(Darwin)
+import Darwin
+#endif
+
+func currentTimestamp() -> String {
+    let formatter = DateFormatter()
+    formatter.dateFormat = "yyyy-MM-dd HH:mm:ss"
+    formatter.timeZone = TimeZone(abbreviation: "UTC")
+    return formatter.string(from: Date())
+}
+
+// MARK: - Due Dates
+func dueDateFormatter() -> DateFormatter {
+    let formatter = DateFormatter()
+    formatter.dateFormat = "yyyy-MM-dd"
+    formatter.locale = Locale(identifier: "en_US_POSIX")
+    formatter.timeZone = TimeZone.current
+    return formatter
+}
+
+func todayDateString() -> String {
+    return dueDateFormatter().string(from: Date())
+}
+
+// Accepts YYYY-MM-DD, "today", "tomorrow" or a relative offset like +3d / +2w.
+func parseDueDate(_ input: String) -> String? {
+    let formatter = dueDateFormatter()
+    let calendar = Calendar.current
+    let today = calendar.startOfDay(for: Date())
+    let lowered = input.lowercased()
+    
+    var offset: DateComponents?
+    switch lowered {
+    case "today":
+        offset = DateComponents(day: 0)
+    case "tomorrow":
+        offset = DateComponents(day: 1)
+    default:
+        if lowered.hasPrefix("+"), let unit = lowered.last, let amount = Int(lowered.dropFirst().dropLast()) {
+            switch unit {
+            case "d":
+                offset = DateComponents(day: amount)
+            case "w":
+                offset = DateComponents(day: amount * 7)
+            default:
+                return nil
+            }
+        }
+    }
+    
+    if let offset = offset {
+        guard let date = calendar.date(byAdding: offset, to: today) else {
+            return nil
+        }
+        return formatter.string(from: date)
+    }
+    
+    guard let date = formatter.date(from: input) else {
+        return nil
+    }
+    return formatter.string(from: date)
+}
+
+// MARK: - Priority
+enum Priority: String, Codable, CaseIterable {
+    case high
+    case medium
+    case low
+    
+    init?(argument: String) {
+        switch argument.lowercased() {
+        case "high", "h", "1":
+            self = .high
+        case "medium", "med", "m", "2":
+            self = .medium
+        case "low", "l", "3":
+            self = .low
+        default:
+            return nil
+        }
+    }
+    
+    // Unprioritised tasks sort between medium and low.
+    static func rank(of priority: Priority?) -> Int {
+        switch priority {
+        case .high?: return 0
+        case .medium?: return 1
+        case nil: return 2
+        case .low?: return 3
+        }
+    }
+    
+    var marker: String {
+        switch self {
+        case .high: return "!!!"
+        case .medium: return "!!"
+        case .low: return "!"
+        }
+    }
+}
+
+// MARK: - Recurrence
+// Stored as a short rule string: "daily", "weekly" or "weekly:mon,thu",
+// "monthly", or "every:3d" for a number of days after each completion.
+enum Recurrence: RawRepresentable, Codable {
+    case daily
+    // Calendar weekday numbers, 1 = Sunday. Empty means the due date's weekday.
+    case weekly([Int])
+    case monthly
+    case afterCompletion(days: Int)
+    
+    static let weekdayNames = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"]
+    
+    init?(rawValue: String) {
+        self.init(argument: rawValue)
+    }
+    
+    // Accepts "daily", "weekly", "weekly mon,thu", "monthly", "every 3d" or "every 2w".
+    init?(argument: String) {
+        let parts = argument.lowercased()
+            .split(whereSeparator: { $0 == " " || $0 == ":" })
+            .map(String.init)
+        guard let kind = parts.first else {
+            return nil
+        }
+        
+        switch kind {
+        case "daily" where parts.count == 1:
+            self = .daily
+        case "monthly" where parts.count == 1:
+            self = .monthly
+        case "weekly":
+            var days: [Int] = []
+            for name in parts.dropFirst().flatMap({ $0.split(separator: ",") }) {
+                guard let index = Recurrence.weekdayNames.firstIndex(of: String(name.prefix(3))) else {
+                    return nil
+                }
+                if !days.contains(index + 1) {
+                    days.append(index + 1)
+                }
+            }
+            self = .weekly(days.sorted())
+        case "every" where parts.count == 2:
+            var amount = parts[1]
+            var multiplier = 1
+            if amount.hasSuffix("w") {
+                multiplier = 7
+                amount.removeLast()
+            } else if amount.hasSuffix("d") {
+                amount.removeLast()
+            }
+            guard let count = Int(amount), count > 0 else {
+                return nil
+            }
+            self = .afterCompletion(days: count * multiplier)
+        default:
+            return nil
+        }
+    }
+    
+    var rawValue: String {
+        switch self {
+        case .daily:
+            return "daily"
+        case .weekly(let days) where days.isEmpty:
+            return "weekly"
+        case .weekly(let days):
+            return "weekly:" + days.map { Recurrence.weekdayNames[$0 - 1] }.joined(separator: ",")
+        case .monthly:
+            return "monthly"
+        case .afterCompletion(let days):
+            return "every:\(days)d"
+        }
+    }
+    
+    var description: String {
+        switch self {
+        case .afterCompletion(let days):
+            return days == 1 ? "1 day after completion" : "\(days) days after completion"
+        default:
+            return rawValue.replacingOccurrences(of: ":", with: " ")
+        }
+    }
+    
+    // The due date of the next occurrence of a task due on `dueDate` (today
+    // when it had none) that is completed today. Always after today.
+    func nextDueDate(after dueDate: String?) -> String {
+        let formatter = dueDateFormatter()
+        let calendar = Calendar.current
+        let today = calendar.startOfDay(for: Date())
+        let anchor = dueDate.flatMap { formatter.date(from: $0) } ?? today
+        
+        if case .afterCompletion(let days) = self {
+            return formatter.string(from: calendar.date(byAdding: .day, value: days, to: today) ?? today)
+        }
+        
+        var weekdays: [Int] = []
+        if case .weekly(let days) = self {
+            weekdays = days.isEmpty ? [calendar.component(.weekday, from: anchor)] : days
+        }
+        
+        // Step from the anchor rather than the previous result so monthly
+        // tasks due on the 31st come back to the 31st after short months.
+        var step = 0
+        var next = anchor
+        repeat {
+            step += 1
+            let candidate: Date?
+            if case .monthly = self {
+                candidate = calendar.date(byAdding: .month, value: step, to: anchor)
+            } else {
+                candidate = calendar.date(byAdding: .day, value: step, to: anchor)
+            }
+            guard let date = candidate else {
+                break
+            }
+            next = date
+        } while next <= today || (!weekdays.isEmpty && !weekdays.contains(calendar.component(.weekday, from: next)))
+        
+        return formatter.string(from: next)
+    }
+}
 
 // MARK: - TodoItem
 struct TodoItem: Codable {
     let id: UInt32
-    let text: String
-    let parentId: UInt32?
+    var text: String
+    var parentId: UInt32?
     let createdAt: String
+    var dueDate: String? = nil
+    var priority: Priority? = nil
+    var tags: [String] = []
+    var position: Int = 0
+    var recurrence: Recurrence? = nil
     
     enum CodingKeys: String, CodingKey {
         case id
         case text
         case parentId = "parent_id"
         case createdAt = "created_at"
+        case dueDate = "due_date"
+        case priority
+        case tags
+        case position
+        case recurrence
+    }
+    
+    // Tags are #word or @word tokens in the text, stored lowercased.
+    static func extractTags(from text: String) -> [String] {
+        var tags: [String] = []
+        for token in text.split(whereSeparator: { $0 == " " || $0 == "\t" }) {
+            guard token.hasPrefix("#") || token.hasPrefix("@") else {
+                continue
+            }
+            
+            let tag = token.lowercased().trimmingCharacters(in: CharacterSet(charactersIn: ".,;:!?)\"'"))
+            if tag.count > 1 && !tags.contains(tag) {
+                tags.append(tag)
+            }
+        }
+        return tags
+    }
+    
+    func hasTag(_ query: String) -> Bool {
+        let query = query.lowercased()
+        if query.hasPrefix("#") || query.hasPrefix("@") {
+            return tags.contains(query)
+        }
+        return tags.contains { $0.dropFirst() == query }
+    }
+    
+    // A copy of this item under a new ID and parent, keeping everything else.
+    func reassigned(id newId: UInt32, parentId newParentId: UInt32?) -> TodoItem {
+        return TodoItem(
+            id: newId,
+            text: text,
+            parentId: newParentId,
+            createdAt: createdAt,
+            dueDate: dueDate,
+            priority: priority,
+            tags: tags,
+            position: position,
+            recurrence: recurrence
+        )
+    }
+    
+    // Priority marker, text, due date and repeat rule as shown in listings.
+    func summary(today: String) -> String {
+        let priorityInfo = priority.map { "\($0.marker) " } ?? ""
+        let dueInfo = dueDescription(today: today).map { " (\($0))" } ?? ""
+        let recurrenceInfo = recurrence.map { " ↻ \($0.description)" } ?? ""
+        return "\(priorityInfo)\(text)\(dueInfo)\(recurrenceInfo)"
+    }
+    
+    func dueDescription(today: String) -> String? {
+        guard let dueDate = dueDate else {
+            return nil
+        }
+        
+        if dueDate < today {
+            return "⚠️ overdue since \(dueDate)"
+        } else if dueDate == today {
+            return "⏰ due today"
+        }
+        return "due \(dueDate)"
     }
 }
 
@@ -22,6 +316,16 @@ struct CompletedTask: Codable {
     let completedAt: String
     let hadSubtasks: Bool
     let subtaskCount: Int
+    // Kept so the task can be reopened as it was; unknown for older entries.
+    var createdAt: String? = nil
+    var parentId: UInt32? = nil
+    var dueDate: String? = nil
+    var priority: Priority? = nil
+    var position: Int? = nil
+    var recurrence: Recurrence? = nil
+    // Every descendant completed along with the task, parents before
+    // children and siblings in the order they were listed.
+    var subtasks: [ArchivedSubtask] = []
     
     enum CodingKeys: String, CodingKey {
         case id
@@ -29,6 +333,121 @@ struct CompletedTask: Codable {
         case completedAt = "completed_at"
         case hadSubtasks = "had_subtasks"
         case subtaskCount = "subtask_count"
+        case createdAt = "created_at"
+        case parentId = "parent_id"
+        case dueDate = "due_date"
+        case priority
+        case position
+        case recurrence
+        case subtasks
+    }
+}
+
+extension CompletedTask {
+    // The archived subtasks directly under the given ID, which is the
+    // task's own ID for the top level.
+    func archivedChildren(of parentId: UInt32) -> [ArchivedSubtask] {
+        return subtasks.filter { $0.parentId == parentId }
+    }
+    
+    // Texts from this task down to one of its archived subtasks.
+    func archivedPath(to subtask: ArchivedSubtask) -> [String] {
+        var path = [subtask.text]
+        var parentId = subtask.parentId
+        while parentId != id, path.count <= subtasks.count, let parent = subtasks.first(where: { $0.id == parentId }) {
+            path.insert(parent.text, at: 0)
+            parentId = parent.parentId
+        }
+        return [text] + path
+    }
+}
+
+struct ArchivedSubtask: Codable {
+    let id: UInt32
+    let text: String
+    let parentId: UInt32
+    let createdAt: String
+    var dueDate: String? = nil
+    var priority: Priority? = nil
+    var position: Int? = nil
+    var recurrence: Recurrence? = nil
+    
+    enum CodingKeys: String, CodingKey {
+        case id
+        case text
+        case parentId = "parent_id"
+        case createdAt = "created_at"
+        case dueDate = "due_date"
+        case priority
+        case position
+        case recurrence
+    }
+}
+
+extension ArchivedSubtask {
+    // Declared in an extension so the memberwise initializer is kept.
+    init(item: TodoItem, parentId: UInt32) {
+        self.init(
+            id: item.id,
+            text: item.text,
+            parentId: parentId,
+            createdAt: item.createdAt,
+            dueDate: item.dueDate,
+            priority: item.priority,
+            position: item.position,
+            recurrence: item.recurrence
+        )
+    }
+    
+    // The same subtask under other IDs, for when an archive is renumbered.
+    func renumbered(id newId: UInt32, parentId newParentId: UInt32) -> ArchivedSubtask {
+        return ArchivedSubtask(
+            id: newId,
+            text: text,
+            parentId: newParentId,
+            createdAt: createdAt,
+            dueDate: dueDate,
+            priority: priority,
+            position: position,
+            recurrence: recurrence
+        )
+    }
+}
+
+// MARK: - TrashedTask
+// A deleted task together with all of its descendants, as they were.
+struct TrashedTask: Codable {
+    let deletedAt: String
+    // Parents before children, with their original IDs.
+    let items: [TodoItem]
+    
+    enum CodingKeys: String, CodingKey {
+        case deletedAt = "deleted_at"
+        case items
+    }
+}
+
+// MARK: - ImportedTask
+// A task read from another format, before it gets an ID in this list.
+// Parent links refer to identifiers within the source file.
+struct ImportedTask {
+    var sourceId: String
+    var parentSourceId: String?
+    var text: String
+    var createdAt: String?
+    var dueDate: String?
+    var priority: Priority?
+    var position: Int?
+    var recurrence: Recurrence?
+    // Set for tasks that were already completed in the source.
+    var completedAt: String?
+    var subtaskCount = 0
+    // Descendants completed along with a completed task.
+    var archivedSubtasks: [ArchivedSubtask] = []
+    
+    init(sourceId: String, text: String) {
+        self.sourceId = sourceId
+        self.text = text
     }
 }
 
@@ -38,12 +457,14 @@ struct TodoList: Codable {
     var nextId: UInt32 = 1
     var completedCount: UInt32 = 0
     var completedHistory: [CompletedTask] = []
+    var trash: [TrashedTask] = []
     
     enum CodingKeys: String, CodingKey {
         case items
         case nextId = "next_id"
         case completedCount = "completed_count"
         case completedHistory = "completed_history"
+        case trash
     }
     
     init() {
@@ -51,28 +472,20 @@ struct TodoList: Codable {
         self.nextId = 1
         self.completedCount = 0
         self.completedHistory = []
-    }
-    
-    init(from decoder: Decoder) throws {
-        let container = try decoder.container(keyedBy: CodingKeys.self)
-        items = try container.decode([String: TodoItem].self, forKey: .items)
-        nextId = try container.decode(UInt32.self, forKey: .nextId)
-        completedCount = try container.decodeIfPresent(UInt32.self, forKey: .completedCount) ?? 0
-        completedHistory = try container.decodeIfPresent([CompletedTask].self, forKey: .completedHistory) ?? []
+        self.trash = []
     }
     
     mutating func addItem(text: String, parentId: UInt32? = nil) -> UInt32 {
         let id = nextId
-        let formatter = DateFormatter()
-        formatter.dateFormat = "yyyy-MM-dd HH:mm:ss"
-        formatter.timeZone = TimeZone(abbreviation: "UTC")
-        let createdAt = formatter.string(from: Date())
+        let createdAt = currentTimestamp()
         
         let item = TodoItem(
             id: id,
             text: text,
             parentId: parentId,
-            createdAt: createdAt
+            createdAt: createdAt,
+            tags: TodoItem.extractTags(from: text),
+            position: nextPosition(parentId: parentId)
         )
         
         items[String(id)] = item
@@ -80,26 +493,31 @@ struct TodoList: Codable {
         return id
     }
     
-    mutating func completeItem(id: UInt32) -> (success: Bool, taskText: String, subtaskCount: Int) {
+    mutating func completeItem(id: UInt32) -> (success: Bool, taskText: String, subtaskCount: Int, nextOccurrenceId: UInt32?) {
         guard let item = items[String(id)] else { 
-            return (false, "", 0) 
+            return (false, "", 0, nil) 
         }
         
         let subItems = getSubItems(parentId: id)
         let subtaskCount = subItems.count
         let hadSubtasks = subtaskCount > 0
         
-        let formatter = DateFormatter()
-        formatter.dateFormat = "yyyy-MM-dd HH:mm:ss"
-        formatter.timeZone = TimeZone(abbreviation: "UTC")
-        let completedAt = formatter.string(from: Date())
+        let completedAt = currentTimestamp()
+        let completedSubtree = subtree(of: id)
         
         let completedTask = CompletedTask(
             id: item.id,
             text: item.text,
             completedAt: completedAt,
             hadSubtasks: hadSubtasks,
-            subtaskCount: subtaskCount
+            subtaskCount: subtaskCount,
+            createdAt: item.createdAt,
+            parentId: item.parentId,
+            dueDate: item.dueDate,
+            priority: item.priority,
+            position: item.position,
+            recurrence: item.recurrence,
+            subtasks: completedSubtree.dropFirst().map { ArchivedSubtask(item: $0, parentId: $0.parentId ?? id) }
         )
         
         completedHistory.append(completedTask)
@@ -107,7 +525,110 @@ struct TodoList: Codable {
         
         _ = deleteItem(id: id)
         
-        return (true, item.text, subtaskCount)
+        var nextOccurrenceId: UInt32?
+        if let recurrence = item.recurrence {
+            nextOccurrenceId = scheduleNextOccurrence(of: completedSubtree, recurrence: recurrence)
+        }
+        return (true, item.text, subtaskCount, nextOccurrenceId)
+    }
+    
+    // Recreates a completed recurring task, subtasks included, in its old
+    // place with the next due date. Completing it has just freed its IDs,
+    // so it comes back under the same ones and scripts referring to them
+    // keep working. Subtask due dates move by the same amount.
+    private mutating func scheduleNextOccurrence(of completedSubtree: [TodoItem], recurrence: Recurrence) -> UInt32? {
+        guard let item = completedSubtree.first else {
+            return nil
+        }
+        
+        for subtask in completedSubtree {
+            items[String(subtask.id)] = subtask
+        }
+        
+        let nextDueDate = recurrence.nextDueDate(after: item.dueDate)
+        items[String(item.id)]?.dueDate = nextDueDate
+        
+        let formatter = dueDateFormatter()
+        guard let oldDue = item.dueDate.flatMap({ formatter.date(from: $0) }),
+              let newDue = formatter.date(from: nextDueDate),
+              let shift = Calendar.current.dateComponents([.day], from: oldDue, to: newDue).day else {
+            return item.id
+        }
+        
+        for subtask in completedSubtree.dropFirst() {
+            guard let subtaskDue = subtask.dueDate.flatMap({ formatter.date(from: $0) }),
+                  let shifted = Calendar.current.date(byAdding: .day, value: shift, to: subtaskDue) else {
+                continue
+            }
+            items[String(subtask.id)]?.dueDate = formatter.string(from: shifted)
+        }
+        return item.id
+    }
+    
+    // Index into completedHistory for a reference like "h1" (newest first).
+    func historyIndex(ref: String) -> Int? {
+        guard ref.lowercased().hasPrefix("h"), let number = Int(ref.dropFirst()),
+              number >= 1, number <= completedHistory.count else {
+            return nil
+        }
+        return completedHistory.count - number
+    }
+    
+    // Moves a completed task and its archived subtasks back into the list,
+    // under its old parent if that is still open, with the due dates,
+    // priorities, repeat rules and order they had. They keep their IDs
+    // unless one has been taken since, say by renumbering, in which case
+    // they all get fresh ones. Returns the reopened task's ID.
+    mutating func reopenCompleted(at index: Int) -> UInt32? {
+        guard completedHistory.indices.contains(index) else {
+            return nil
+        }
+        
+        let task = completedHistory.remove(at: index)
+        completedCount = completedCount > 0 ? completedCount - 1 : 0
+        
+        var reopened = [TodoItem(
+            id: task.id,
+            text: task.text,
+            parentId: nil,
+            createdAt: task.createdAt ?? currentTimestamp(),
+            dueDate: task.dueDate,
+            priority: task.priority,
+            tags: TodoItem.extractTags(from: task.text),
+            position: task.position ?? 0,
+            recurrence: task.recurrence
+        )]
+        for (offset, subtask) in task.subtasks.enumerated() {
+            reopened.append(TodoItem(
+                id: subtask.id,
+                text: subtask.text,
+                parentId: subtask.parentId,
+                createdAt: subtask.createdAt,
+                dueDate: subtask.dueDate,
+                priority: subtask.priority,
+                tags: TodoItem.extractTags(from: subtask.text),
+                position: subtask.position ?? offset,
+                recurrence: subtask.recurrence
+            ))
+        }
+        
+        let parentId = task.parentId.flatMap { items[String($0)] != nil ? $0 : nil }
+        guard reopened.allSatisfy({ items[String($0.id)] == nil }) else {
+            return insertSubtree(reopened, under: parentId)[task.id]
+        }
+        
+        // The task goes back to its old place among its siblings if that
+        // is still free, otherwise to the end.
+        let siblingPositions = items.values.filter { $0.parentId == parentId }.map { $0.position }
+        reopened[0].parentId = parentId
+        if task.position == nil || siblingPositions.contains(reopened[0].position) {
+            reopened[0].position = nextPosition(parentId: parentId)
+        }
+        for item in reopened {
+            items[String(item.id)] = item
+        }
+        nextId = max(nextId, task.id + 1, (task.subtasks.map { $0.id }.max() ?? 0) + 1)
+        return task.id
     }
     
     mutating func deleteItem(id: UInt32) -> Bool {
@@ -121,14 +642,305 @@ struct TodoList: Codable {
         return items.removeValue(forKey: String(id)) != nil
     }
     
+    // Deletes a task and its subtasks, keeping them in the trash.
+    mutating func trashItem(id: UInt32) -> Bool {
+        let deletedSubtree = subtree(of: id)
+        guard !deletedSubtree.isEmpty else {
+            return false
+        }
+        
+        trash.append(TrashedTask(deletedAt: currentTimestamp(), items: deletedSubtree))
+        return deleteItem(id: id)
+    }
+    
+    // Trash entries are referred to as t1, t2, ... with t1 the most
+    // recently deleted.
+    func trashEntries() -> [(ref: String, entry: TrashedTask)] {
+        return trash.reversed().enumerated().map { (ref: "t\($0.offset + 1)", entry: $0.element) }
+    }
+    
+    func trashIndex(ref: String) -> Int? {
+        guard ref.lowercased().hasPrefix("t"), let number = Int(ref.dropFirst()),
+              number >= 1, number <= trash.count else {
+            return nil
+        }
+        return trash.count - number
+    }
+    
+    // Puts a trashed subtree back under its old parent if that still exists,
+    // with fresh IDs. Returns the restored task's ID.
+    mutating func restoreTrashed(at index: Int) -> UInt32? {
+        guard trash.indices.contains(index), let root = trash[index].items.first else {
+            return nil
+        }
+        
+        let entry = trash.remove(at: index)
+        let parentId = root.parentId.flatMap { items[String($0)] != nil ? $0 : nil }
+        return insertSubtree(entry.items, under: parentId)[root.id]
+    }
+    
+    // Drops trash entries deleted more than `days` days ago and returns how
+    // many went. Timestamps sort as strings, so no date parsing is needed.
+    mutating func purgeTrash(olderThan days: Int, now: Date = Date()) -> Int {
+        let formatter = DateFormatter()
+        formatter.dateFormat = "yyyy-MM-dd HH:mm:ss"
+        formatter.timeZone = TimeZone(abbreviation: "UTC")
+        let cutoff = formatter.string(from: now.addingTimeInterval(-Double(days) * 86_400))
+        
+        let countBefore = trash.count
+        trash.removeAll { $0.deletedAt < cutoff }
+        return countBefore - trash.count
+    }
+    
+    mutating func editItem(id: UInt32, text: String) -> Bool {
+        guard items[String(id)] != nil else {
+            return false
+        }
+        
+        items[String(id)]?.text = text
+        items[String(id)]?.tags = TodoItem.extractTags(from: text)
+        return true
+    }
+    
+    // Re-homes a task; its descendants follow because they point at it.
+    mutating func moveItem(id: UInt32, newParentId: UInt32?) -> Bool {
+        guard items[String(id)] != nil else {
+            return false
+        }
+        
+        items[String(id)]?.parentId = newParentId
+        items[String(id)]?.position = nextPosition(parentId: newParentId)
+        return true
+    }
+    
+    func nextPosition(parentId: UInt32?) -> Int {
+        let siblings = items.values.filter { $0.parentId == parentId }
+        return (siblings.map { $0.position }.max() ?? -1) + 1
+    }
+    
+    // Siblings sharing the item's priority, in display order. Manual ordering
+    // happens within this group since priority always sorts first.
+    func priorityGroup(of item: TodoItem) -> [TodoItem] {
+        let siblings = item.parentId.map { getSubItems(parentId: $0) } ?? getRootItems()
+        return siblings.filter { $0.priority == item.priority }
+    }
+    
+    func orderIndex(id: UInt32) -> Int? {
+        guard let item = items[String(id)] else {
+            return nil
+        }
+        return priorityGroup(of: item).firstIndex { $0.id == id }
+    }
+    
+    // Moves a task to the given zero-based index within its priority group.
+    mutating func reorderItem(id: UInt32, toIndex index: Int) -> Bool {
+        guard let item = items[String(id)] else {
+            return false
+        }
+        
+        var group = priorityGroup(of: item)
+        guard let currentIndex = group.firstIndex(where: { $0.id == id }) else {
+            return false
+        }
+        
+        let targetIndex = min(max(index, 0), group.count - 1)
+        guard targetIndex != currentIndex else {
+            return false
+        }
+        
+        group.remove(at: currentIndex)
+        group.insert(item, at: targetIndex)
+        for (position, sibling) in group.enumerated() {
+            items[String(sibling.id)]?.position = position
+        }
+        return true
+    }
+    
+    // The task followed by all of its descendants, parents before children.
+    func subtree(of id: UInt32) -> [TodoItem] {
+        guard let item = items[String(id)] else {
+            return []
+        }
+        
+        var subtree = [item]
+        for child in getSubItems(parentId: id) {
+            subtree.append(contentsOf: self.subtree(of: child.id))
+        }
+        return subtree
+    }
+    
+    // Adds items (parents before children) under fresh IDs, keeping their
+    // relative structure. Items whose parent is not among them go under
+    // `parentId`. Returns the mapping from old to new IDs.
+    @discardableResult
+    mutating func insertSubtree(_ subtreeItems: [TodoItem], under parentId: UInt32?) -> [UInt32: UInt32] {
+        var idMap: [UInt32: UInt32] = [:]
+        for item in subtreeItems {
+            let newId = nextId
+            nextId += 1
+            idMap[item.id] = newId
+            
+            var newItem: TodoItem
+            if let oldParentId = item.parentId, let newParentId = idMap[oldParentId] {
+                newItem = item.reassigned(id: newId, parentId: newParentId)
+            } else {
+                newItem = item.reassigned(id: newId, parentId: parentId)
+                newItem.position = nextPosition(parentId: parentId)
+            }
+            items[String(newId)] = newItem
+        }
+        return idMap
+    }
+    
+    // Adds tasks read from another format. Open tasks get fresh IDs with their
+    // parent links remapped; completed ones go straight into the history.
+    mutating func importTasks(_ tasks: [ImportedTask]) -> (added: Int, archived: Int) {
+        let openTasks = tasks.filter { $0.completedAt == nil }
+        var newIds: [UInt32] = []
+        var idMap: [String: UInt32] = [:]
+        for task in openTasks {
+            newIds.append(nextId)
+            if idMap[task.sourceId] == nil {
+                idMap[task.sourceId] = nextId
+            }
+            nextId += 1
+        }
+        
+        let firstRootPosition = nextPosition(parentId: nil)
+        for (index, task) in openTasks.enumerated() {
+            let id = newIds[index]
+            var parentId = task.parentSourceId.flatMap { idMap[$0] }
+            // Drop parent links that would make a task its own ancestor.
+            if let candidate = parentId, candidate == id || isDescendant(candidate, of: id) {
+                parentId = nil
+            }
+            
+            items[String(id)] = TodoItem(
+                id: id,
+                text: task.text,
+                parentId: parentId,
+                createdAt: task.createdAt ?? currentTimestamp(),
+                dueDate: task.dueDate,
+                priority: task.priority,
+                tags: TodoItem.extractTags(from: task.text),
+                position: task.position ?? nextPosition(parentId: parentId),
+                recurrence: task.recurrence
+            )
+        }
+        
+        // Imported root tasks go after the existing ones, in the order the
+        // file gave them, rather than in between.
+        let importedRoots = newIds.filter { items[String($0)]?.parentId == nil }.enumerated().sorted { lhs, rhs in
+            let lhsPosition = items[String(lhs.element)]?.position ?? 0
+            let rhsPosition = items[String(rhs.element)]?.position ?? 0
+            return (lhsPosition, lhs.offset) < (rhsPosition, rhs.offset)
+        }
+        for (offset, root) in importedRoots.enumerated() {
+            items[String(root.element)]?.position = firstRootPosition + offset
+        }
+        
+        let completedTasks = tasks.filter { $0.completedAt != nil }
+        for task in completedTasks {
+            // The file's IDs may be missing or clash with this list's, so the
+            // task and its archived subtasks are numbered like new todos.
+            let id = nextId
+            nextId += 1
+            var archivedIds: [UInt32: UInt32] = [:]
+            if let sourceId = UInt32(task.sourceId) {
+                archivedIds[sourceId] = id
+            }
+            var subtaskIds: [UInt32] = []
+            for subtask in task.archivedSubtasks {
+                subtaskIds.append(nextId)
+                if archivedIds[subtask.id] == nil {
+                    archivedIds[subtask.id] = nextId
+                }
+                nextId += 1
+            }
+            let subtasks = task.archivedSubtasks.enumerated().map { index, subtask in
+                subtask.renumbered(id: subtaskIds[index], parentId: archivedIds[subtask.parentId] ?? id)
+            }
+            
+            completedHistory.append(CompletedTask(
+                id: id,
+                text: task.text,
+                completedAt: task.completedAt ?? currentTimestamp(),
+                hadSubtasks: task.subtaskCount > 0,
+                subtaskCount: task.subtaskCount,
+                createdAt: task.createdAt,
+                parentId: task.parentSourceId.flatMap { idMap[$0] },
+                dueDate: task.dueDate,
+                priority: task.priority,
+                position: task.position,
+                recurrence: task.recurrence,
+                subtasks: subtasks
+            ))
+            completedCount += 1
+        }
+        
+        return (openTasks.count, completedTasks.count)
+    }
+    
+    func isDescendant(_ id: UInt32, of ancestorId: UInt32) -> Bool {
+        var current = items[String(id)]?.parentId
+        while let parentId = current {
+            if parentId == ancestorId {
+                return true
+            }
+            current = items[String(parentId)]?.parentId
+        }
+        return false
+    }
+    
+    mutating func setDueDate(id: UInt32, dueDate: String?) -> Bool {
+        guard items[String(id)] != nil else {
+            return false
+        }
+        
+        items[String(id)]?.dueDate = dueDate
+        return true
+    }
+    
+    mutating func setRecurrence(id: UInt32, recurrence: Recurrence?) -> Bool {
+        guard items[String(id)] != nil else {
+            return false
+        }
+        
+        items[String(id)]?.recurrence = recurrence
+        return true
+    }
+    
+    mutating func setPriority(id: UInt32, priority: Priority?) -> Bool {
+        guard items[String(id)] != nil else {
+            return false
+        }
+        
+        items[String(id)]?.priority = priority
+        return true
+    }
+    
     func getRootItems() -> [TodoItem] {
         let rootItems = items.values.filter { $0.parentId == nil }
-        return rootItems.sorted { $0.id < $1.id }
+        return sortedSiblings(rootItems)
     }
     
     func getSubItems(parentId: UInt32) -> [TodoItem] {
         let subItems = items.values.filter { $0.parentId == parentId }
-        return subItems.sorted { $0.id < $1.id }
+        return sortedSiblings(subItems)
+    }
+    
+    private func sortedSiblings(_ siblings: [TodoItem]) -> [TodoItem] {
+        return siblings.sorted {
+            let lhsRank = Priority.rank(of: $0.priority)
+            let rhsRank = Priority.rank(of: $1.priority)
+            if lhsRank != rhsRank {
+                return lhsRank < rhsRank
+            }
+            if $0.position != $1.position {
+                return $0.position < $1.position
+            }
+            return $0.id < $1.id
+        }
     }
     
     mutating func renumberItems() {
@@ -143,13 +955,7 @@ struct TodoList: Codable {
             oldToNewId[item.id] = newId
             currentId += 1
             
-            let newItem = TodoItem(
-                id: newId,
-                text: item.text,
-                parentId: newParentId,
-                createdAt: item.createdAt
-            )
-            newItems[String(newId)] = newItem
+            newItems[String(newId)] = item.reassigned(id: newId, parentId: newParentId)
             
             let children = getSubItems(parentId: item.id)
             for child in children {
@@ -165,202 +971,1665 @@ struct TodoList: Codable {
         nextId = currentId
     }
     
-    func display() {
-        let rootItems = getRootItems()
-        if rootItems.isEmpty {
-            print("No todos found. Use 'jdi add <text>' to add a new todo.")
-            return
+    // All items in display order: each parent followed by its subtree.
+    func orderedItems() -> [TodoItem] {
+        var ordered: [TodoItem] = []
+        
+        func visit(_ item: TodoItem) {
+            ordered.append(item)
+            for child in getSubItems(parentId: item.id) {
+                visit(child)
+            }
         }
         
-        for item in rootItems {
-            displayItem(item: item, indentLevel: 0)
+        for rootItem in getRootItems() {
+            visit(rootItem)
         }
+        return ordered
     }
     
-    func displayItem(item: TodoItem, indentLevel: Int) {
+    // The chain of items from the root down to and including the given item.
+    func path(to item: TodoItem) -> [TodoItem] {
+        var path = [item]
+        var current = item
+        while let parentId = current.parentId, let parent = items[String(parentId)] {
+            path.insert(parent, at: 0)
+            current = parent
+        }
+        return path
+    }
+    
+    func search(matching matches: (String) -> Bool) -> (open: [TodoItem], completed: [CompletedTask]) {
+        let openHits = orderedItems().filter { matches($0.text) }
+        let completedHits = completedHistory.reversed().filter { task in
+            matches(task.text) || task.subtasks.contains { matches($0.text) }
+        }
+        return (openHits, completedHits)
+    }
+    
+    // Counts open tasks per tag, most used first.
+    func tagCounts() -> [(tag: String, count: Int)] {
+        var counts: [String: Int] = [:]
+        for item in items.values {
+            for tag in item.tags {
+                counts[tag, default: 0] += 1
+            }
+        }
+        
+        return counts
+            .map { (tag: $0.key, count: $0.value) }
+            .sorted { $0.count != $1.count ? $0.count > $1.count : $0.tag < $1.tag }
+    }
+    
+    // IDs of tasks carrying every given tag, plus their ancestors for context.
+    func visibleIds(matchingTags tagQueries: [String]) -> Set<UInt32> {
+        var visible = Set<UInt32>()
+        for item in items.values where tagQueries.allSatisfy({ item.hasTag($0) }) {
+            var current: TodoItem? = item
+            while let node = current, !visible.contains(node.id) {
+                visible.insert(node.id)
+                current = node.parentId.flatMap { items[String($0)] }
+            }
+        }
+        return visible
+    }
+    
+    func node(for item: TodoItem, visibleIds: Set<UInt32>? = nil) -> TodoNode {
+        let children = getSubItems(parentId: item.id)
+            .filter { visibleIds?.contains($0.id) ?? true }
+            .map { node(for: $0, visibleIds: visibleIds) }
+        return TodoNode(item: item, children: children)
+    }
+    
+    func tree(visibleIds: Set<UInt32>? = nil) -> [TodoNode] {
+        return getRootItems()
+            .filter { visibleIds?.contains($0.id) ?? true }
+            .map { node(for: $0, visibleIds: visibleIds) }
+    }
+    
+    func display(visibleIds: Set<UInt32>? = nil) {
+        let rootItems = getRootItems().filter { visibleIds?.contains($0.id) ?? true }
+        if rootItems.isEmpty {
+            if visibleIds != nil {
+                print("No todos match that filter.")
+            } else {
+                print("No todos found. Use 'jdi add <text>' to add a new todo.")
+            }
+            return
+        }
+        
+        for item in rootItems {
+            displayItem(item: item, indentLevel: 0, visibleIds: visibleIds)
+        }
+    }
+    
+    func displayItem(item: TodoItem, indentLevel: Int, visibleIds: Set<UInt32>? = nil) {
         let indent = String(repeating: "  ", count: indentLevel)
-        print("\(indent)[\(item.id)] ○ \(item.text)")
+        print("\(indent)[\(item.id)] ○ \(item.summary(today: todayDateString()))")
         
-        let subItems = getSubItems(parentId: item.id)
+        let subItems = getSubItems(parentId: item.id).filter { visibleIds?.contains($0.id) ?? true }
         for subItem in subItems {
-            displayItem(item: subItem, indentLevel: indentLevel + 1)
+            displayItem(item: subItem, indentLevel: indentLevel + 1, visibleIds: visibleIds)
         }
     }
     
-    func displayStats() {
-        print("📊 Completion Statistics:")
-        print("Total completed: \(completedCount) tasks\n")
-        
+    // History entries are referred to as h1, h2, ... with h1 the most
+    // recently completed.
+    func historyEntries() -> [(ref: String, task: CompletedTask)] {
+        return completedHistory.reversed().enumerated().map { (ref: "h\($0.offset + 1)", task: $0.element) }
+    }
+    
+    func displayHistory(limit: Int?) {
         if completedHistory.isEmpty {
             print("No completed tasks yet.")
             return
         }
         
-        print("Recently completed:")
-        let recentTasks = completedHistory.suffix(10).reversed()
-        for task in recentTasks {
-            let subtaskInfo = task.hadSubtasks ? " (\(task.subtaskCount) subtasks)" : ""
-            print("[\(task.id)] ✓ \(task.text)\(subtaskInfo) (\(task.completedAt))")
+        let entries = historyEntries()
+        for entry in entries.prefix(limit ?? entries.count) {
+            print("\(entry.ref) [\(entry.task.id)] ✓ \(entry.task.text) (\(entry.task.completedAt))")
+            if entry.task.subtasks.isEmpty && entry.task.hadSubtasks {
+                // Completed before subtasks were archived.
+                print("  (\(entry.task.subtaskCount) subtask(s), details not kept)")
+            }
+            displayArchivedSubtasks(of: entry.task, parentId: entry.task.id, indentLevel: 1)
+        }
+        
+        if let limit = limit, entries.count > limit {
+            print("… \(entries.count - limit) older, see 'jdi history --all'")
+        }
+    }
+    
+    func displayTrash(retentionDays: Int) {
+        if trash.isEmpty {
+            print("Trash is empty.")
+            return
+        }
+        
+        let retention = retentionDays > 0 ? "deleted after \(retentionDays) days" : "kept until emptied"
+        print("🗑  Trash (\(retention)):")
+        for (ref, entry) in trashEntries() {
+            var depths: [UInt32: Int] = [:]
+            for (index, item) in entry.items.enumerated() {
+                let depth = index == 0 ? 0 : item.parentId.flatMap { depths[$0] }.map { $0 + 1 } ?? 1
+                depths[item.id] = depth
+                
+                let indent = String(repeating: "  ", count: depth)
+                let deletedInfo = index == 0 ? " (deleted \(entry.deletedAt))" : ""
+                let label = index == 0 ? "\(ref) " : "  "
+                print("\(label)\(indent)[\(item.id)] \(item.text)\(deletedInfo)")
+            }
+        }
+    }
+    
+    func displayArchivedSubtasks(of task: CompletedTask, parentId: UInt32, indentLevel: Int) {
+        let indent = String(repeating: "  ", count: indentLevel)
+        for subtask in task.archivedChildren(of: parentId) {
+            print("\(indent)[\(subtask.id)] ✓ \(subtask.text)")
+            displayArchivedSubtasks(of: task, parentId: subtask.id, indentLevel: indentLevel + 1)
+        }
+    }
+}
+
+// MARK: - JSON Output
+// A todo with its subtasks nested under it. Optional fields are always
+// written, as null when unset, so the shape is stable for scripts.
+struct TodoNode: Encodable {
+    let item: TodoItem
+    let children: [TodoNode]
+    
+    enum CodingKeys: String, CodingKey {
+        case id
+        case text
+        case parentId = "parent_id"
+        case createdAt = "created_at"
+        case dueDate = "due_date"
+        case priority
+        case tags
+        case position
+        case recurrence
+        case children
+    }
+    
+    func encode(to encoder: Encoder) throws {
+        var container = encoder.container(keyedBy: CodingKeys.self)
+        try container.encode(item.id, forKey: .id)
+        try container.encode(item.text, forKey: .text)
+        try container.encode(item.parentId, forKey: .parentId)
+        try container.encode(item.createdAt, forKey: .createdAt)
+        try container.encode(item.dueDate, forKey: .dueDate)
+        try container.encode(item.priority, forKey: .priority)
+        try container.encode(item.tags, forKey: .tags)
+        try container.encode(item.position, forKey: .position)
+        try container.encode(item.recurrence, forKey: .recurrence)
+        try container.encode(children, forKey: .children)
+    }
+}
+
+struct DoneOutput: Encodable {
+    let completed: CompletedTask
+    let nextOccurrence: TodoNode?
+    
+    enum CodingKeys: String, CodingKey {
+        case completed
+        case nextOccurrence = "next_occurrence"
+    }
+    
+    func encode(to encoder: Encoder) throws {
+        var container = encoder.container(keyedBy: CodingKeys.self)
+        try container.encode(completed, forKey: .completed)
+        try container.encode(nextOccurrence, forKey: .nextOccurrence)
+    }
+}
+
+struct HistoryEntryOutput: Encodable {
+    let ref: String
+    let task: CompletedTask
+}
+
+struct TrashEntryOutput: Encodable {
+    let ref: String
+    let entry: TrashedTask
+}
+
+struct ListOutput: Encodable {
+    let list: String
+    let items: [TodoNode]
+}
+
+// completed_history and everything after it only cover --since/--until.
+struct StatsOutput: Encodable {
+    let completedCount: UInt32
+    let stats: CompletionStats
+    
+    enum CodingKeys: String, CodingKey {
+        case completedCount = "completed_count"
+        case completedHistory = "completed_history"
+        case since
+        case until
+        case currentStreak = "current_streak"
+        case longestStreak = "longest_streak"
+        case perDay = "per_day"
+        case perWeek = "per_week"
+        case perMonth = "per_month"
+        case weekdays
+    }
+    
+    func encode(to encoder: Encoder) throws {
+        let formatter = dueDateFormatter()
+        var container = encoder.container(keyedBy: CodingKeys.self)
+        try container.encode(completedCount, forKey: .completedCount)
+        try container.encode(stats.tasks, forKey: .completedHistory)
+        try container.encode(stats.since.map { formatter.string(from: $0) }, forKey: .since)
+        try container.encode(formatter.string(from: stats.until), forKey: .until)
+        try container.encode(stats.currentStreak, forKey: .currentStreak)
+        try container.encode(stats.longestStreak, forKey: .longestStreak)
+        try container.encode(stats.buckets(.day), forKey: .perDay)
+        try container.encode(stats.buckets(.week), forKey: .perWeek)
+        try container.encode(stats.buckets(.month), forKey: .perMonth)
+        let weekdays = zip(CompletionStats.weekdayNames, stats.weekdayTotals()).map { ($0.lowercased(), $1) }
+        try container.encode(Dictionary(uniqueKeysWithValues: weekdays), forKey: .weekdays)
+    }
+}
+
+func printJSON<T: Encodable>(_ value: T) throws {
+    let encoder = JSONEncoder()
+    encoder.outputFormatting = [.prettyPrinted, .sortedKeys]
+    do {
+        let data = try encoder.encode(value)
+        print(String(decoding: data, as: UTF8.self))
+    } catch {
+        throw JDIError.failed("Could not encode JSON output: \(error)")
+    }
+}
+
+// MARK: - TodoStore
+struct TodoStore: Codable {
+    static let defaultListName = "default"
+    
+    var lists: [String: TodoList] = [TodoStore.defaultListName: TodoList()]
+    var currentList: String = TodoStore.defaultListName
+    // Calendar files kept up to date after every save, keyed by list name.
+    var calendarSync: [String: String] = [:]
+    // Days deleted tasks stay in the trash; 0 keeps them until emptied.
+    var trashRetentionDays = TodoStore.defaultTrashRetentionDays
+    // Older files are upgraded on load (see Migrations.swift), so every
+    // field above is always present when decoding.
+    var schemaVersion = currentSchemaVersion
+    
+    static let defaultTrashRetentionDays = 30
+    
+    enum CodingKeys: String, CodingKey {
+        case schemaVersion = "schema_version"
+        case lists
+        case currentList = "current_list"
+        case calendarSync = "calendar_sync"
+        case trashRetentionDays = "trash_retention_days"
+    }
+    
+    init() {}
+    
+    // Applies the retention period to a list's trash.
+    func purgeExpiredTrash(from list: inout TodoList) {
+        guard trashRetentionDays > 0 else {
+            return
+        }
+        _ = list.purgeTrash(olderThan: trashRetentionDays)
+    }
+    
+    static func isValidListName(_ name: String) -> Bool {
+        let allowed = CharacterSet.alphanumerics.union(CharacterSet(charactersIn: "-_."))
+        return !name.isEmpty && name.unicodeScalars.allSatisfy { allowed.contains($0) }
+    }
+    
+    func displayLists() {
+        for name in lists.keys.sorted() {
+            let marker = name == currentList ? "*" : " "
+            let openCount = lists[name]?.items.count ?? 0
+            print("\(marker) \(name) (\(openCount) open)")
+        }
+    }
+}
+
+// MARK: - Journal
+struct JournalEntry: Codable {
+    let action: String
+    let timestamp: String
+    // The lists touched by the action, keyed by name, as they were before it.
+    var snapshots: [String: TodoList]
+    
+    enum CodingKeys: String, CodingKey {
+        case action
+        case timestamp
+        case snapshots
+        case snapshot
+    }
+    
+    init(action: String, timestamp: String, snapshots: [String: TodoList]) {
+        self.action = action
+        self.timestamp = timestamp
+        self.snapshots = snapshots
+    }
+    
+    init(from decoder: Decoder) throws {
+        let container = try decoder.container(keyedBy: CodingKeys.self)
+        action = try container.decode(String.self, forKey: .action)
+        timestamp = try container.decode(String.self, forKey: .timestamp)
+        if let snapshot = try container.decodeIfPresent(TodoList.self, forKey: .snapshot) {
+            snapshots = [TodoStore.defaultListName: snapshot]
+        } else {
+            snapshots = try container.decode([String: TodoList].self, forKey: .snapshots)
+        }
+    }
+    
+    func encode(to encoder: Encoder) throws {
+        var container = encoder.container(keyedBy: CodingKeys.self)
+        try container.encode(action, forKey: .action)
+        try container.encode(timestamp, forKey: .timestamp)
+        try container.encode(snapshots, forKey: .snapshots)
+    }
+    
+    // The same lists as this entry, taken from the given store.
+    func counterpart(in store: TodoStore) -> JournalEntry {
+        var current: [String: TodoList] = [:]
+        for name in snapshots.keys {
+            current[name] = store.lists[name] ?? TodoList()
+        }
+        return JournalEntry(action: action, timestamp: currentTimestamp(), snapshots: current)
+    }
+}
+
+// Undo/redo stacks of list snapshots. Each entry holds the state to
+// restore, so undoing brings back subtasks, counters and history exactly.
+struct Journal: Codable {
+    static let maxEntries = 50
+    
+    var undoStack: [JournalEntry] = []
+    var redoStack: [JournalEntry] = []
+    
+    enum CodingKeys: String, CodingKey {
+        case undoStack = "undo"
+        case redoStack = "redo"
+    }
+    
+    init() {}
+    
+    mutating func record(action: String, before: [String: TodoList]) {
+        undoStack.append(JournalEntry(action: action, timestamp: currentTimestamp(), snapshots: before))
+        if undoStack.count > Journal.maxEntries {
+            undoStack.removeFirst(undoStack.count - Journal.maxEntries)
+        }
+        redoStack.removeAll()
+    }
+    
+    // Rewrites every list snapshot on both stacks, e.g. to drop trash that
+    // must not come back through undo or redo.
+    mutating func updateSnapshots(_ body: (_ name: String, _ snapshot: inout TodoList) -> Void) {
+        for index in undoStack.indices {
+            for name in undoStack[index].snapshots.keys {
+                body(name, &undoStack[index].snapshots[name]!)
+            }
+        }
+        for index in redoStack.indices {
+            for name in redoStack[index].snapshots.keys {
+                body(name, &redoStack[index].snapshots[name]!)
+            }
+        }
+    }
+    
+    mutating func undo(current: TodoStore) -> JournalEntry? {
+        guard let entry = undoStack.popLast() else {
+            return nil
+        }
+        redoStack.append(entry.counterpart(in: current))
+        return entry
+    }
+    
+    mutating func redo(current: TodoStore) -> JournalEntry? {
+        guard let entry = redoStack.popLast() else {
+            return nil
+        }
+        undoStack.append(entry.counterpart(in: current))
+        return entry
+    }
+}
+
+// MARK: - File Management
+struct StoragePaths {
+    let dataFile: String
+    // Whether the path came from XDG defaults rather than an explicit override.
+    let isDefaultLocation: Bool
+    
+    var directory: String {
+        return (dataFile as NSString).deletingLastPathComponent
+    }
+    
+    var journalFile: String {
+        return "\((dataFile as NSString).deletingPathExtension).journal.json"
+    }
+    
+    var lockFile: String {
+        return "\(dataFile).lock"
+    }
+}
+
+// Resolution order: --file, $JDI_FILE, $JDI_DIR/todos.json,
+// $XDG_DATA_HOME/jdi/todos.json, then ~/.local/share/jdi/todos.json.
+func resolveStoragePaths(fileOverride: String?) throws -> StoragePaths {
+    let environment = ProcessInfo.processInfo.environment
+    
+    if let file = fileOverride ?? environment["JDI_FILE"], !file.isEmpty {
+        return StoragePaths(dataFile: absolutePath(file), isDefaultLocation: false)
+    }
+    
+    if let dir = environment["JDI_DIR"], !dir.isEmpty {
+        return StoragePaths(dataFile: "\(absolutePath(dir))/todos.json", isDefaultLocation: false)
+    }
+    
+    // The XDG spec says relative values must be ignored.
+    if let dataHome = environment["XDG_DATA_HOME"], dataHome.hasPrefix("/") {
+        return StoragePaths(dataFile: "\(dataHome)/jdi/todos.json", isDefaultLocation: true)
+    }
+    
+    guard let home = environment["HOME"], !home.isEmpty else {
+        throw JDIError.storage("HOME is not set. Set JDI_FILE, JDI_DIR or XDG_DATA_HOME to choose where todos are stored.")
+    }
+    return StoragePaths(dataFile: "\(home)/.local/share/jdi/todos.json", isDefaultLocation: true)
+}
+
+// Expands ~ and resolves relative paths against the current directory, so
+// a bare file name still has a directory to create and lock in.
+func absolutePath(_ path: String) -> String {
+    return URL(fileURLWithPath: (path as NSString).expandingTildeInPath).standardized.path
+}
+
+// Moves data from the original ~/.todo_cli.json location on first run.
+func migrateLegacyStorage(to paths: StoragePaths) throws {
+    let fileManager = FileManager.default
+    guard paths.isDefaultLocation,
+          let home = ProcessInfo.processInfo.environment["HOME"], !home.isEmpty,
+          !fileManager.fileExists(atPath: paths.dataFile) else {
+        return
+    }
+    
+    let legacyFile = "\(home)/.todo_cli.json"
+    guard fileManager.fileExists(atPath: legacyFile) else {
+        return
+    }
+    
+    try fileManager.moveItem(atPath: legacyFile, toPath: paths.dataFile)
+    
+    let legacyJournal = "\(home)/.todo_cli_journal.json"
+    if fileManager.fileExists(atPath: legacyJournal) && !fileManager.fileExists(atPath: paths.journalFile) {
+        try fileManager.moveItem(atPath: legacyJournal, toPath: paths.journalFile)
+    }
+    try? fileManager.removeItem(atPath: "\(legacyFile).lock")
+    
+    printError("Moved \(legacyFile) to \(paths.dataFile)")
+}
+
+// Exclusive advisory lock held for a whole load/modify/save cycle so that
+// concurrent jdi processes cannot overwrite each other's changes.
+final class FileLock {
+    private let fd: Int32
+    private var isHeld = true
+    
+    private init(fd: Int32) {
+        self.fd = fd
+    }
+    
+    static func acquire(path: String, timeout: TimeInterval = 5) throws -> FileLock {
+        let fd = open(path, O_CREAT | O_RDWR, 0o644)
+        guard fd >= 0 else {
+            throw JDIError.lock("Could not open lock file \(path): \(String(cString: strerror(errno)))")
+        }
+        
+        let deadline = Date().addingTimeInterval(timeout)
+        while flock(fd, LOCK_EX | LOCK_NB) != 0 {
+            guard errno == EAGAIN || errno == EINTR, Date() < deadline else {
+                close(fd)
+                throw JDIError.lock("Another jdi process is holding the lock on \(path). Try again in a moment.")
+            }
+            usleep(100_000)
+        }
+        
+        return FileLock(fd: fd)
+    }
+    
+    // Safe to call more than once.
+    func release() {
+        guard isHeld else {
+            return
+        }
+        isHeld = false
+        _ = flock(fd, LOCK_UN)
+        close(fd)
+    }
+}
+
+// Writes to a temporary file in the same directory and renames it over the
+// destination, so a crash mid-write never leaves a truncated file behind.
+func writeFileAtomically(_ data: Data, to path: String) throws {
+    let tempPath = "\(path).tmp-\(getpid())"
+    let tempURL = URL(fileURLWithPath: tempPath)
+    
+    do {
+        try data.write(to: tempURL)
+        
+        let handle = try FileHandle(forWritingTo: tempURL)
+        handle.synchronizeFile()
+        handle.closeFile()
+        
+        guard rename(tempPath, path) == 0 else {
+            throw JDIError.storage("Could not write \(path): \(String(cString: strerror(errno)))")
+        }
+    } catch {
+        try? FileManager.default.removeItem(at: tempURL)
+        throw error
+    }
+}
+
+func ensureDataDirExists(_ paths: StoragePaths) throws {
+    let fileManager = FileManager.default
+    
+    if !fileManager.fileExists(atPath: paths.directory) {
+        do {
+            try fileManager.createDirectory(atPath: paths.directory, withIntermediateDirectories: true, attributes: nil)
+        } catch {
+            throw JDIError.storage("Could not create \(paths.directory): \(error.localizedDescription)")
+        }
+    }
+}
+
+func loadTodoStore(_ paths: StoragePaths) throws -> TodoStore {
+    let filePath = paths.dataFile
+    
+    guard FileManager.default.fileExists(atPath: filePath) else {
+        return TodoStore()
+    }
+    
+    let data: Data
+    do {
+        data = try Data(contentsOf: URL(fileURLWithPath: filePath))
+    } catch {
+        throw JDIError.storage("Could not read \(filePath): \(error.localizedDescription)")
+    }
+    
+    var json: JSONObject
+    do {
+        json = try parseStoreJSON(data)
+    } catch {
+        throw unreadableTodoFile(at: filePath, error)
+    }
+    
+    let version = schemaVersion(of: json)
+    try checkSchemaVersion(version, of: filePath)
+    guard version < currentSchemaVersion else {
+        if let store = try? JSONDecoder().decode(TodoStore.self, from: data) {
+            return store
+        }
+        // A current file missing fields, say after a hand edit, gets their
+        // defaults back before it is given up on.
+        fillStoreDefaults(&json)
+        return try decodeTodoStore(json, at: filePath)
+    }
+    
+    var backupPaths: [String] = []
+    do {
+        try migrateStoreJSON(&json) { stepVersion, stepJSON in
+            let contents = try stepVersion == version ? data : JSONSerialization.data(withJSONObject: stepJSON, options: .prettyPrinted)
+            backupPaths.append(try backupTodoFile(at: filePath, version: stepVersion, contents: contents))
+        }
+    } catch {
+        throw JDIError.storage("Could not back up \(filePath) before upgrading it: \(error.localizedDescription)")
+    }
+    
+    let store = try decodeTodoStore(json, at: filePath)
+    try saveTodoStore(store, paths)
+    printError("Upgraded \(filePath) from schema version \(version) to \(currentSchemaVersion). Backups taken before each step: \(backupPaths.joined(separator: ", "))")
+    return store
+}
+
+func decodeTodoStore(_ json: JSONObject, at filePath: String) throws -> TodoStore {
+    do {
+        let data = try JSONSerialization.data(withJSONObject: json)
+        return try JSONDecoder().decode(TodoStore.self, from: data)
+    } catch {
+        throw unreadableTodoFile(at: filePath, error)
+    }
+}
+
+// Never carry on with an empty list after a failed decode: the next save
+// would overwrite the data we failed to parse. The file is moved aside
+// and the returned error says where to.
+func unreadableTodoFile(at filePath: String, _ error: Error) -> JDIError {
+    let quarantinePath: String
+    do {
+        quarantinePath = try quarantineTodoFile(at: filePath)
+    } catch let quarantineError {
+        return JDIError.storage("Could not decode \(filePath): \(error)\nIt could not be quarantined either: \(quarantineError.localizedDescription)")
+    }
+    return JDIError.storage("Could not decode \(filePath): \(error)\nThe unreadable file was moved to \(quarantinePath)")
+}
+
+func quarantineTodoFile(at filePath: String) throws -> String {
+    let formatter = DateFormatter()
+    formatter.dateFormat = "yyyyMMdd-HHmmss"
+    formatter.timeZone = TimeZone(abbreviation: "UTC")
+    let timestamp = formatter.string(from: Date())
+    
+    let quarantinePath = "\(filePath).corrupt-\(timestamp)"
+    try FileManager.default.moveItem(atPath: filePath, toPath: quarantinePath)
+    return quarantinePath
+}
+
+func saveTodoStore(_ store: TodoStore, _ paths: StoragePaths) throws {
+    try ensureDataDirExists(paths)
+    
+    do {
+        let encoder = JSONEncoder()
+        encoder.outputFormatting = .prettyPrinted
+        let data = try encoder.encode(store)
+        
+        try writeFileAtomically(data, to: paths.dataFile)
+    } catch let error as JDIError {
+        throw error
+    } catch {
+        throw JDIError.storage("Could not save todos: \(error.localizedDescription)")
+    }
+    
+    syncCalendarExports(store)
+}
+
+func loadJournal(_ paths: StoragePaths) -> Journal {
+    let filePath = paths.journalFile
+    
+    guard FileManager.default.fileExists(atPath: filePath) else {
+        return Journal()
+    }
+    
+    do {
+        var json = try parseStoreJSON(Data(contentsOf: URL(fileURLWithPath: filePath)))
+        upgradeJournalJSON(&json)
+        let data = try JSONSerialization.data(withJSONObject: json)
+        return try JSONDecoder().decode(Journal.self, from: data)
+    } catch {
+        printError("Warning: could not read undo history, starting a new one: \(error)")
+        return Journal()
+    }
+}
+
+func saveJournal(_ journal: Journal, _ paths: StoragePaths) throws {
+    do {
+        let data = try JSONEncoder().encode(journal)
+        try writeFileAtomically(data, to: paths.journalFile)
+    } catch let error as JDIError {
+        throw error
+    } catch {
+        throw JDIError.storage("Could not save undo history: \(error.localizedDescription)")
+    }
+}
+
+// Saves a mutated store and records the lists it replaced so it can be undone.
+func saveMutation(_ store: TodoStore, action: String, before: [String: TodoList], paths: StoragePaths) throws {
+    // Expired trash goes with the next change to its list. It is purged
+    // from the undo history too, so no undo or redo can bring it back.
+    var store = store
+    for name in before.keys {
+        if var list = store.lists[name] {
+            store.purgeExpiredTrash(from: &list)
+            store.lists[name] = list
+        }
+    }
+    
+    var journal = loadJournal(paths)
+    journal.record(action: action, before: before)
+    journal.updateSnapshots { _, snapshot in
+        store.purgeExpiredTrash(from: &snapshot)
+    }
+    try saveTodoStore(store, paths)
+    try saveJournal(journal, paths)
+}
+
+// Restores the lists recorded by the latest undo (or redo) entry.
+func stepJournal(_ store: inout TodoStore, paths: StoragePaths, redo: Bool) throws -> JournalEntry? {
+    var journal = loadJournal(paths)
+    guard let entry = redo ? journal.redo(current: store) : journal.undo(current: store) else {
+        return nil
+    }
+    
+    store.lists.merge(entry.snapshots) { _, snapshot in snapshot }
+    try saveTodoStore(store, paths)
+    try saveJournal(journal, paths)
+    return entry
+}
+
+// One locked load/modify/save cycle against a list, for callers that do not
+// hold the lock for their whole run. Nothing is saved if `body` throws.
+func mutateList(_ listName: String, paths: StoragePaths, action: String, _ body: (inout TodoList) throws -> Void) throws -> TodoList {
+    let lock = try FileLock.acquire(path: paths.lockFile)
+    defer { lock.release() }
+    
+    var store = try loadTodoStore(paths)
+    var todoList = store.lists[listName] ?? TodoList()
+    let original = todoList
+    try body(&todoList)
+    
+    store.lists[listName] = todoList
+    try saveMutation(store, action: action, before: [listName: original], paths: paths)
+    return todoList
+}
+
+// MARK: - Editor
+// Opens $EDITOR (or $VISUAL, falling back to vi) on the given text and
+// returns what was saved, with lines joined into a single line.
+func editTextInEditor(_ text: String) throws -> String {
+    let environment = ProcessInfo.processInfo.environment
+    let editor = environment["EDITOR"] ?? environment["VISUAL"] ?? "vi"
+    let tempURL = URL(fileURLWithPath: NSTemporaryDirectory())
+        .appendingPathComponent("jdi-edit-\(getpid()).txt")
+    defer { try? FileManager.default.removeItem(at: tempURL) }
+    
+    do {
+        try (text + "\n").write(to: tempURL, atomically: true, encoding: .utf8)
+        
+        // Run through the shell so editors configured with arguments work.
+        let process = Process()
+        process.executableURL = URL(fileURLWithPath: "/bin/sh")
+        process.arguments = ["-c", "\(editor) \"$1\"", "sh", tempURL.path]
+        try process.run()
+        process.waitUntilExit()
+        
+        guard process.terminationStatus == 0 else {
+            throw JDIError.failed("Editor exited with status \(process.terminationStatus)")
+        }
+        
+        let edited = try String(contentsOf: tempURL, encoding: .utf8)
+        return edited
+            .split(whereSeparator: { $0.isNewline })
+            .map { $0.trimmingCharacters(in: .whitespaces) }
+            .filter { !$0.isEmpty }
+            .joined(separator: " ")
+    } catch let error as JDIError {
+        throw error
+    } catch {
+        throw JDIError.failed("Could not run editor '\(editor)': \(error.localizedDescription)")
+    }
+}
+
+// MARK: - Errors
+// Every failure a command can report. Each class maps to its own exit code
+// so scripts can tell them apart; the codes are listed in `jdi help`.
+enum JDIError: Error, CustomStringConvertible {
+    case usage(String, usage: String? = nil)
+    case notFound(String)
+    case storage(String)
+    case lock(String)
+    case failed(String)
+    
+    var exitCode: Int32 {
+        switch self {
+        case .failed: return 1
+        case .usage: return 2
+        case .notFound: return 3
+        case .storage: return 4
+        case .lock: return 5
+        }
+    }
+    
+    var description: String {
+        switch self {
+        case .usage(let message, _), .notFound(let message), .storage(let message), .lock(let message), .failed(let message):
+            return message
+        }
+    }
+}
+
+func printError(_ message: String) {
+    FileHandle.standardError.write(Data((message + "\n").utf8))
+}
+
+// MARK: - Command Line Interface
+func printUsage() {
+    print("Usage:")
+    print("  jdi add <text>           Add a new todo (shorthand: a)")
+    print("  jdi sub <id> <text>      Add a subtask (shorthand: s)")
+    print("  jdi done <id>            Complete and archive todo (shorthand: d)")
+    print("  jdi delete <id>          Move a todo and its subtasks to the trash (shorthand: del)")
+    print("  jdi edit <id> [text]     Replace a todo's text, or open $EDITOR without text (shorthand: e)")
+    print("  jdi move <id> <parent>   Move a todo and its subtasks under another todo, or 'root' (shorthand: mv)")
+    print("  jdi up <id>              Move a todo up among its siblings")
+    print("  jdi down <id>            Move a todo down among its siblings")
+    print("  jdi order <id> <n>       Move a todo to position n among siblings of the same priority")
+    print("  jdi due <id> <date>      Set a due date: YYYY-MM-DD, today, tomorrow, +3d, +2w or none")
+    print("  jdi pri <id> <level>     Set priority: high, medium, low or none (shorthand: p)")
+    print("  jdi repeat <id> <rule>   Repeat on completion: daily, weekly [mon,thu], monthly, every 3d, or none")
+    print("  jdi list [tag...]        List all todos, or only those tagged e.g. '#work' (shorthand: l)")
+    print("  jdi tags                 List tags with their open task counts")
+    print("  jdi search [-r] <query>  Search open and completed todos, -r for a regex (shorthand: f)")
+    print("  jdi renumber             Compact IDs to 1..n (changes existing IDs)")
+    print("  jdi undo                 Undo the last change (shorthand: u)")
+    print("  jdi redo                 Redo the last undone change")
+    print("  jdi trash                Show deleted todos, newest (t1) first")
+    print("  jdi trash empty          Permanently remove everything in the trash, not undoable")
+    print("  jdi trash retention [d]  Show or set how many days deleted todos are kept, or 'never'")
+    print("  jdi restore <ref>        Move a deleted todo and its subtasks back into the list, e.g. t1")
+    print("  jdi history [n|--all]    Show completed todos with their subtasks, newest (h1) first (shorthand: hist)")
+    print("  jdi reopen <ref>         Move a completed todo and its subtasks back into the list, e.g. h1 (shorthand: ro)")
+    print("  jdi stats                Show completion charts, streaks and a weekday heatmap (shorthand: st)")
+    print("                           Limit to a range with --since and --until, e.g. --since -30d")
+    print("  jdi tui                  Open the interactive full-screen view")
+    print("  jdi lists                Show all lists, marking the current one")
+    print("  jdi use <list>           Switch to a list, creating it if needed")
+    print("  jdi transfer <id> <list> Move a todo and its subtasks to another list")
+    print("  jdi export [--format f]  Print todos as todotxt, markdown or ics, or write them with --output <file>")
+    print("                           Add --sync to keep an ics file up to date, --no-sync to stop")
+    print("  jdi import <file>        Import todos from a todo.txt or Markdown (.md) checklist file")
+    print("  jdi migrate [--dry-run]  Upgrade the data file to the current schema, or show what would change")
+    print("  jdi help                 Show this help message (shorthand: h)")
+    print("")
+    print("Options, given before the command:")
+    print("  --list <name>            Run the command against another list")
+    print("  --file <path>            Use another data file (also $JDI_FILE or $JDI_DIR)")
+    print("  --json                   Print JSON for add, sub, done, delete, list, history and stats")
+    print("")
+    print("Exit codes:")
+    print("  0  success")
+    print("  1  other failure, such as the editor exiting with an error")
+    print("  2  usage error: unknown command, missing or invalid arguments")
+    print("  3  a todo, parent or list was not found")
+    print("  4  storage error: the data file could not be read, decoded or written")
+    print("  5  another jdi process holds the lock")
+}
+
+// Parses the --format/--output options shared by import and export.
+func parseFormatOptions(_ arguments: ArraySlice<String>, allowOutput: Bool) throws -> (format: String?, output: String?) {
+    var format: String?
+    var output: String?
+    var index = arguments.startIndex
+    
+    while index < arguments.endIndex {
+        let argument = arguments[index]
+        let isFormat = argument == "--format" || argument == "-f"
+        let isOutput = allowOutput && (argument == "--output" || argument == "-o")
+        guard isFormat || isOutput else {
+            throw JDIError.usage("Unknown option '\(argument)'")
+        }
+        guard index + 1 < arguments.endIndex else {
+            throw JDIError.usage("'\(argument)' requires a value")
+        }
+        
+        if isFormat {
+            format = arguments[index + 1].lowercased()
+        } else {
+            output = arguments[index + 1]
         }
+        index += 2
     }
+    
+    return (format, output)
 }
 
-// MARK: - File Management
-func getConfigPath() -> String {
-    let homeDir = ProcessInfo.processInfo.environment["HOME"] ?? ""
-    return "\(homeDir)"
-}
-
-func getTodoFilePath() -> String {
-    return "\(getConfigPath())/.todo_cli.json"
+struct GlobalOptions {
+    var listName: String?
+    var filePath: String?
+    var json = false
 }
 
-func ensureConfigDirExists() throws {
-    let configPath = getConfigPath()
-    let fileManager = FileManager.default
+// Pulls global flags from before the command. Everything from the command
+// on, or after a bare "--", is passed through untouched, so task text like
+// "fix --json output" is left alone.
+func parseGlobalOptions(_ arguments: [String]) throws -> (options: GlobalOptions, arguments: [String]) {
+    var options = GlobalOptions()
+    var remaining = Array(arguments.prefix(1))
+    var index = 1
     
-    if !fileManager.fileExists(atPath: configPath) {
-        try fileManager.createDirectory(atPath: configPath, withIntermediateDirectories: true, attributes: nil)
+    while index < arguments.count {
+        let argument = arguments[index]
+        if argument == "--" {
+            remaining.append(contentsOf: arguments[(index + 1)...])
+            break
+        } else if argument == "--json" {
+            options.json = true
+        } else if let flag = ["--list", "--file"].first(where: { argument == $0 || argument.hasPrefix("\($0)=") }) {
+            let value: String
+            if argument == flag {
+                guard index + 1 < arguments.count else {
+                    throw JDIError.usage("'\(flag)' requires a value")
+                }
+                value = arguments[index + 1]
+                index += 1
+            } else {
+                value = String(argument.dropFirst(flag.count + 1))
+            }
+            
+            if flag == "--list" {
+                options.listName = value
+            } else {
+                options.filePath = value
+            }
+        } else {
+            remaining.append(contentsOf: arguments[index...])
+            break
+        }
+        index += 1
     }
+    
+    return (options, remaining)
 }
 
-func loadTodoList() -> TodoList {
-    let filePath = getTodoFilePath()
+func run(_ arguments: [String]) throws {
+    let (options, args) = try parseGlobalOptions(arguments)
     
-    guard FileManager.default.fileExists(atPath: filePath) else {
-        return TodoList()
-    }
+    let paths = try resolveStoragePaths(fileOverride: options.filePath)
+    try ensureDataDirExists(paths)
+    var lock = try FileLock.acquire(path: paths.lockFile)
+    defer { lock.release() }
     
     do {
-        let data = try Data(contentsOf: URL(fileURLWithPath: filePath))
-        let todoList = try JSONDecoder().decode(TodoList.self, from: data)
-        return todoList
+        try migrateLegacyStorage(to: paths)
+    } catch let error as JDIError {
+        throw error
     } catch {
-        print("Error loading todos: \(error)")
-        return TodoList()
+        throw JDIError.storage("Could not move the old data file: \(error.localizedDescription)")
     }
-}
-
-func saveTodoList(_ todoList: TodoList) {
-    do {
-        try ensureConfigDirExists()
-        
-        let encoder = JSONEncoder()
-        encoder.outputFormatting = .prettyPrinted
-        let data = try encoder.encode(todoList)
-        
-        let filePath = getTodoFilePath()
-        try data.write(to: URL(fileURLWithPath: filePath))
-    } catch {
-        print("Error saving todos: \(error)")
+    
+    if args.count > 1 && args[1] == "migrate" {
+        try runMigrateCommand(Array(args[2...]), paths: paths)
+        return
+    }
+    
+    var store = try loadTodoStore(paths)
+    let listName = options.listName ?? store.currentList
+    if options.listName != nil && store.lists[listName] == nil {
+        throw JDIError.notFound("List '\(listName)' does not exist. Use 'jdi use \(listName)' to create it")
+    }
+    
+    var todoList = store.lists[listName] ?? TodoList()
+    // Expired trash stays in the file until the list next changes, but is
+    // already gone for listing and restoring.
+    store.purgeExpiredTrash(from: &todoList)
+    var original = todoList
+    
+    // Writes the mutated list back into the store, journaling its old state.
+    func commit(_ action: String) throws {
+        store.lists[listName] = todoList
+        try saveMutation(store, action: action, before: [listName: original], paths: paths)
     }
-}
-
-// MARK: - Command Line Interface
-func printUsage() {
-    print("Usage:")
-    print("  jdi add <text>           Add a new todo (shorthand: a)")
-    print("  jdi sub <id> <text>      Add a subtask (shorthand: s)")
-    print("  jdi done <id>            Complete and archive todo (shorthand: d)")
-    print("  jdi delete <id>          Delete todo without archiving (shorthand: del)")
-    print("  jdi list                 List all todos (shorthand: l)")
-    print("  jdi stats                Show completion statistics (shorthand: st)")
-    print("  jdi help                 Show this help message (shorthand: h)")
-}
-
-func main() {
-    let args = CommandLine.arguments
     
     guard args.count > 1 else {
-        var todoList = loadTodoList()
-        todoList.renumberItems()
-        saveTodoList(todoList)
-        todoList.display()
+        if options.json {
+            try printJSON(ListOutput(list: listName, items: todoList.tree()))
+        } else {
+            todoList.display()
+        }
         return
     }
     
     let command = args[1]
-    var todoList = loadTodoList()
     
     switch command {
     case "add", "a":
         guard args.count >= 3 else {
-            print("Error: 'add' requires text")
-            printUsage()
-            return
+            throw JDIError.usage("'add' requires text")
         }
         
         let text = args[2...].joined(separator: " ")
         let id = todoList.addItem(text: text, parentId: nil)
-        saveTodoList(todoList)
-        print("Added todo \(id): \(text)")
+        try commit("add \(id): \(text)")
+        if options.json, let item = todoList.items[String(id)] {
+            try printJSON(["created": todoList.node(for: item)])
+        } else {
+            print("Added todo \(id): \(text)")
+        }
         
     case "sub", "s":
         guard args.count >= 4 else {
-            print("Error: 'sub' requires parent ID and text")
-            print("Usage: jdi sub <parent_id> <text>")
-            return
+            throw JDIError.usage("'sub' requires parent ID and text", usage: "jdi sub <parent_id> <text>")
         }
         
         guard let parentId = UInt32(args[2]) else {
-            print("Error: Invalid parent ID")
-            return
+            throw JDIError.usage("Invalid parent ID")
         }
         
         guard todoList.items[String(parentId)] != nil else {
-            print("Error: Parent todo \(parentId) does not exist")
-            return
+            throw JDIError.notFound("Parent todo \(parentId) does not exist")
         }
         
         let text = args[3...].joined(separator: " ")
         let id = todoList.addItem(text: text, parentId: parentId)
-        saveTodoList(todoList)
-        print("Added subtask \(id) to [\(parentId)]: \(text)")
+        try commit("sub \(id): \(text)")
+        if options.json, let item = todoList.items[String(id)] {
+            try printJSON(["created": todoList.node(for: item)])
+        } else {
+            print("Added subtask \(id) to [\(parentId)]: \(text)")
+        }
         
     case "done", "d":
         guard args.count == 3, let id = UInt32(args[2]) else {
-            print("Error: 'done' requires a valid ID")
-            printUsage()
-            return
+            throw JDIError.usage("'done' requires a valid ID")
         }
         
         let result = todoList.completeItem(id: id)
         if result.success {
-            saveTodoList(todoList)
-            let subtaskInfo = result.subtaskCount > 0 ? " and \(result.subtaskCount) subtask(s)" : ""
-            print("✓ Completed and removed todo \(id)\(subtaskInfo): \(result.taskText)")
+            try commit("done \(id): \(result.taskText)")
+            let nextOccurrence = result.nextOccurrenceId.flatMap { todoList.items[String($0)] }
+            if options.json, let completedTask = todoList.completedHistory.last {
+                try printJSON(DoneOutput(completed: completedTask, nextOccurrence: nextOccurrence.map { todoList.node(for: $0) }))
+            } else {
+                let subtaskInfo = result.subtaskCount > 0 ? " and \(result.subtaskCount) subtask(s)" : ""
+                print("✓ Completed and removed todo \(id)\(subtaskInfo): \(result.taskText)")
+                if let nextOccurrence = nextOccurrence {
+                    print("↻ Next occurrence is todo \(nextOccurrence.id), due \(nextOccurrence.dueDate ?? "today")")
+                }
+            }
         } else {
-            print("Error: Todo \(id) not found")
+            throw JDIError.notFound("Todo \(id) not found")
         }
         
     case "delete", "del":
         guard args.count == 3, let id = UInt32(args[2]) else {
-            print("Error: 'delete' requires a valid ID")
-            printUsage()
+            throw JDIError.usage("'delete' requires a valid ID")
+        }
+        
+        let deletedNode = todoList.items[String(id)].map { todoList.node(for: $0) }
+        if todoList.trashItem(id: id) {
+            try commit("delete \(id)")
+            if options.json, let deletedNode = deletedNode {
+                try printJSON(["deleted": deletedNode])
+            } else {
+                print("Moved todo \(id) to the trash. Run 'jdi restore t1' to bring it back")
+            }
+        } else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+    case "edit", "e":
+        guard args.count >= 3, let id = UInt32(args[2]) else {
+            throw JDIError.usage("'edit' requires a valid ID", usage: "jdi edit <id> [new text]")
+        }
+        
+        guard let item = todoList.items[String(id)] else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+        let newText: String
+        if args.count >= 4 {
+            newText = args[3...].joined(separator: " ")
+        } else {
+            // Other jdi calls would time out on the lock for as long as the
+            // editor is open, so it is dropped and taken again afterwards.
+            lock.release()
+            newText = try editTextInEditor(item.text)
+            lock = try FileLock.acquire(path: paths.lockFile)
+            
+            // The list may have changed while the editor was open.
+            store = try loadTodoStore(paths)
+            todoList = store.lists[listName] ?? TodoList()
+            original = todoList
+        }
+        
+        guard let currentText = todoList.items[String(id)]?.text else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+        guard !newText.isEmpty else {
+            throw JDIError.usage("Todo text cannot be empty")
+        }
+        
+        guard newText != currentText else {
+            print("No changes to todo \(id)")
             return
         }
         
-        if todoList.deleteItem(id: id) {
-            saveTodoList(todoList)
-            print("Deleted todo \(id)")
+        _ = todoList.editItem(id: id, text: newText)
+        try commit("edit \(id): \(newText)")
+        print("Updated todo \(id): \(newText)")
+        
+    case "move", "mv":
+        guard args.count == 4, let id = UInt32(args[2]) else {
+            throw JDIError.usage("'move' requires a valid ID and a new parent", usage: "jdi move <id> <new_parent_id|root>")
+        }
+        
+        guard todoList.items[String(id)] != nil else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+        let newParentId: UInt32?
+        if args[3] == "root" {
+            newParentId = nil
+        } else if let parentId = UInt32(args[3]) {
+            guard todoList.items[String(parentId)] != nil else {
+                throw JDIError.notFound("Parent todo \(parentId) does not exist")
+            }
+            guard parentId != id, !todoList.isDescendant(parentId, of: id) else {
+                throw JDIError.usage("Cannot move todo \(id) under itself or one of its subtasks")
+            }
+            newParentId = parentId
+        } else {
+            throw JDIError.usage("Invalid parent ID")
+        }
+        
+        _ = todoList.moveItem(id: id, newParentId: newParentId)
+        try commit("move \(id)")
+        if let newParentId = newParentId {
+            print("Moved todo \(id) under [\(newParentId)]")
+        } else {
+            print("Moved todo \(id) to the top level")
+        }
+        
+    case "up", "down":
+        guard args.count == 3, let id = UInt32(args[2]) else {
+            throw JDIError.usage("'\(command)' requires a valid ID")
+        }
+        
+        guard let index = todoList.orderIndex(id: id) else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+        let targetIndex = command == "up" ? index - 1 : index + 1
+        if todoList.reorderItem(id: id, toIndex: targetIndex) {
+            try commit("\(command) \(id)")
+            print("Moved todo \(id) \(command)")
+        } else {
+            let edge = command == "up" ? "first" : "last"
+            print("Todo \(id) is already \(edge) among its siblings of the same priority")
+        }
+        
+    case "order":
+        guard args.count == 4, let id = UInt32(args[2]), let position = Int(args[3]), position >= 1 else {
+            throw JDIError.usage("'order' requires a valid ID and a position starting at 1", usage: "jdi order <id> <position>")
+        }
+        
+        guard todoList.items[String(id)] != nil else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+        if todoList.reorderItem(id: id, toIndex: position - 1) {
+            try commit("order \(id)")
+            print("Moved todo \(id) to position \((todoList.orderIndex(id: id) ?? 0) + 1)")
+        } else {
+            print("Todo \(id) is already at that position")
+        }
+        
+    case "due":
+        guard args.count == 4, let id = UInt32(args[2]) else {
+            throw JDIError.usage("'due' requires a valid ID and a date", usage: "jdi due <id> <YYYY-MM-DD|today|tomorrow|+Nd|+Nw|none>")
+        }
+        
+        let dueDate: String?
+        if args[3] == "none" || args[3] == "clear" {
+            dueDate = nil
+        } else if let parsed = parseDueDate(args[3]) {
+            dueDate = parsed
+        } else {
+            throw JDIError.usage("Invalid date '\(args[3])'")
+        }
+        
+        if todoList.setDueDate(id: id, dueDate: dueDate) {
+            try commit("due \(id)")
+            if let dueDate = dueDate {
+                print("Set due date of todo \(id) to \(dueDate)")
+            } else {
+                print("Cleared due date of todo \(id)")
+            }
+        } else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+    case "repeat", "rec":
+        guard args.count >= 4, let id = UInt32(args[2]) else {
+            throw JDIError.usage("'repeat' requires a valid ID and a rule", usage: "jdi repeat <id> <daily|weekly [mon,thu]|monthly|every <n>d|none>")
+        }
+        
+        let rule = args[3...].joined(separator: " ")
+        let recurrence: Recurrence?
+        if rule == "none" || rule == "clear" {
+            recurrence = nil
+        } else if let parsed = Recurrence(argument: rule) {
+            recurrence = parsed
+        } else {
+            throw JDIError.usage("Invalid repeat rule '\(rule)'", usage: "jdi repeat <id> <daily|weekly [mon,thu]|monthly|every <n>d|none>")
+        }
+        
+        if todoList.setRecurrence(id: id, recurrence: recurrence) {
+            try commit("repeat \(id)")
+            if let recurrence = recurrence {
+                print("Todo \(id) now repeats \(recurrence.description)")
+            } else {
+                print("Todo \(id) no longer repeats")
+            }
+        } else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+    case "pri", "priority", "p":
+        guard args.count == 4, let id = UInt32(args[2]) else {
+            throw JDIError.usage("'pri' requires a valid ID and a priority", usage: "jdi pri <id> <high|medium|low|none>")
+        }
+        
+        let priority: Priority?
+        if args[3] == "none" || args[3] == "clear" {
+            priority = nil
+        } else if let parsed = Priority(argument: args[3]) {
+            priority = parsed
+        } else {
+            throw JDIError.usage("Invalid priority '\(args[3])'")
+        }
+        
+        if todoList.setPriority(id: id, priority: priority) {
+            try commit("pri \(id)")
+            if let priority = priority {
+                print("Set priority of todo \(id) to \(priority.rawValue)")
+            } else {
+                print("Cleared priority of todo \(id)")
+            }
         } else {
-            print("Error: Todo \(id) not found")
+            throw JDIError.notFound("Todo \(id) not found")
         }
         
     case "list", "l":
+        let tagQueries = Array(args[2...])
+        let visibleIds = tagQueries.isEmpty ? nil : todoList.visibleIds(matchingTags: tagQueries)
+        if options.json {
+            try printJSON(ListOutput(list: listName, items: todoList.tree(visibleIds: visibleIds)))
+        } else {
+            todoList.display(visibleIds: visibleIds)
+        }
+        
+    case "search", "f":
+        var queryArgs = Array(args[2...])
+        let useRegex = queryArgs.first == "--regex" || queryArgs.first == "-r"
+        if useRegex {
+            queryArgs.removeFirst()
+        }
+        
+        guard !queryArgs.isEmpty else {
+            throw JDIError.usage("'search' requires a query", usage: "jdi search [--regex] <query>")
+        }
+        
+        let query = queryArgs.joined(separator: " ")
+        let matches: (String) -> Bool
+        if useRegex {
+            guard let regex = try? NSRegularExpression(pattern: query, options: [.caseInsensitive]) else {
+                throw JDIError.usage("Invalid regular expression '\(query)'")
+            }
+            matches = { text in
+                regex.firstMatch(in: text, options: [], range: NSRange(text.startIndex..., in: text)) != nil
+            }
+        } else {
+            matches = { text in
+                text.range(of: query, options: [.caseInsensitive]) != nil
+            }
+        }
+        
+        let results = todoList.search(matching: matches)
+        if results.open.isEmpty && results.completed.isEmpty {
+            print("No todos match '\(query)'")
+            return
+        }
+        
+        if !results.open.isEmpty {
+            print("Open:")
+            for item in results.open {
+                let path = todoList.path(to: item).map { $0.text }.joined(separator: " › ")
+                print("[\(item.id)] ○ \(path)")
+            }
+        }
+        
+        if !results.completed.isEmpty {
+            if !results.open.isEmpty {
+                print("")
+            }
+            print("Completed:")
+            for task in results.completed {
+                if matches(task.text) {
+                    print("[\(task.id)] ✓ \(task.text) (completed \(task.completedAt))")
+                }
+                for subtask in task.subtasks where matches(subtask.text) {
+                    let path = task.archivedPath(to: subtask).joined(separator: " › ")
+                    print("[\(subtask.id)] ✓ \(path) (completed \(task.completedAt))")
+                }
+            }
+        }
+        
+    case "tags":
+        let counts = todoList.tagCounts()
+        if counts.isEmpty {
+            print("No tags found. Add #tag or @tag to a todo's text to tag it.")
+            return
+        }
+        
+        for entry in counts {
+            print("\(entry.tag) (\(entry.count) open)")
+        }
+        
+    case "renumber":
         todoList.renumberItems()
-        saveTodoList(todoList)
+        try commit("renumber")
+        print("Renumbered todos")
         todoList.display()
         
+    case "undo", "u":
+        guard let entry = try stepJournal(&store, paths: paths, redo: false) else {
+            print("Nothing to undo")
+            return
+        }
+        print("↶ Undid: \(entry.action)")
+        
+    case "redo":
+        guard let entry = try stepJournal(&store, paths: paths, redo: true) else {
+            print("Nothing to redo")
+            return
+        }
+        print("↷ Redid: \(entry.action)")
+        
+    case "lists":
+        store.displayLists()
+        
+    case "use":
+        guard args.count == 3 else {
+            throw JDIError.usage("'use' requires a list name", usage: "jdi use <list>")
+        }
+        
+        let name = args[2]
+        guard TodoStore.isValidListName(name) else {
+            throw JDIError.usage("List names may only contain letters, digits, '-', '_' and '.'")
+        }
+        
+        let isNew = store.lists[name] == nil
+        if isNew {
+            store.lists[name] = TodoList()
+        }
+        store.currentList = name
+        try saveTodoStore(store, paths)
+        print(isNew ? "Created and switched to list '\(name)'" : "Switched to list '\(name)'")
+        
+    case "transfer", "xfer":
+        guard args.count == 4, let id = UInt32(args[2]) else {
+            throw JDIError.usage("'transfer' requires a valid ID and a list name", usage: "jdi transfer <id> <list>")
+        }
+        
+        let targetName = args[3]
+        guard targetName != listName else {
+            throw JDIError.usage("Todo \(id) is already in list '\(targetName)'")
+        }
+        
+        guard var targetList = store.lists[targetName] else {
+            throw JDIError.notFound("List '\(targetName)' does not exist. Use 'jdi use \(targetName)' to create it")
+        }
+        
+        let subtree = todoList.subtree(of: id)
+        guard !subtree.isEmpty else {
+            throw JDIError.notFound("Todo \(id) not found")
+        }
+        
+        let targetBefore = targetList
+        let idMap = targetList.insertSubtree(subtree, under: nil)
+        _ = todoList.deleteItem(id: id)
+        
+        store.lists[listName] = todoList
+        store.lists[targetName] = targetList
+        try saveMutation(store, action: "transfer \(id) to \(targetName)", before: [listName: original, targetName: targetBefore], paths: paths)
+        
+        let subtaskInfo = subtree.count > 1 ? " and \(subtree.count - 1) subtask(s)" : ""
+        print("Moved todo \(id)\(subtaskInfo) to list '\(targetName)' as [\(idMap[id] ?? 0)]")
+        
+    case "export":
+        var exportArgs = Array(args[2...])
+        let enableSync = exportArgs.contains("--sync")
+        let disableSync = exportArgs.contains("--no-sync")
+        exportArgs.removeAll { $0 == "--sync" || $0 == "--no-sync" }
+        
+        let formatOptions = try parseFormatOptions(exportArgs[...], allowOutput: true)
+        let exportUsage = "jdi export [--format todotxt|markdown|ics] [--output <file> [--sync]]"
+        
+        if disableSync {
+            guard let syncedPath = store.calendarSync.removeValue(forKey: listName) else {
+                print("List '\(listName)' is not being synced to a calendar file")
+                return
+            }
+            try saveTodoStore(store, paths)
+            print("Stopped keeping \(syncedPath) up to date")
+            return
+        }
+        
+        let format = formatOptions.format ?? "todotxt"
+        let content: String
+        switch format {
+        case "todotxt", "todo.txt", "txt":
+            content = exportTodoTxt(todoList)
+        case "markdown", "md":
+            content = exportMarkdown(todoList)
+        case "ics", "ical", "icalendar":
+            content = exportICalendar(todoList, listName: listName)
+        default:
+            throw JDIError.usage("Unknown export format '\(format)'", usage: exportUsage)
+        }
+        
+        if enableSync && !["ics", "ical", "icalendar"].contains(format) {
+            throw JDIError.usage("'--sync' is only supported for the ics format", usage: exportUsage)
+        }
+        
+        guard let outputPath = formatOptions.output else {
+            if enableSync {
+                throw JDIError.usage("'--sync' requires --output", usage: exportUsage)
+            }
+            print(content, terminator: "")
+            return
+        }
+        
+        // Synced paths are written on later saves, possibly from another directory.
+        let absoluteOutputPath = absolutePath(outputPath)
+        do {
+            try writeFileAtomically(Data(content.utf8), to: absoluteOutputPath)
+        } catch let error as JDIError {
+            throw error
+        } catch {
+            throw JDIError.storage("Could not write \(outputPath): \(error.localizedDescription)")
+        }
+        
+        if enableSync {
+            store.calendarSync[listName] = absoluteOutputPath
+            try saveTodoStore(store, paths)
+            print("Exported \(listName) to \(outputPath) and will keep it up to date")
+        } else {
+            print("Exported \(listName) to \(outputPath)")
+        }
+        
+    case "import":
+        guard args.count >= 3 else {
+            throw JDIError.usage("'import' requires a file", usage: "jdi import <file> [--format todotxt|markdown]")
+        }
+        
+        let inputPath = (args[2] as NSString).expandingTildeInPath
+        let formatOptions = try parseFormatOptions(args[3...], allowOutput: false)
+        guard FileManager.default.fileExists(atPath: inputPath) else {
+            throw JDIError.notFound("File \(args[2]) not found")
+        }
+        
+        let content: String
+        do {
+            content = try String(contentsOfFile: inputPath, encoding: .utf8)
+        } catch {
+            throw JDIError.storage("Could not read \(args[2]): \(error.localizedDescription)")
+        }
+        
+        let fileExtension = (inputPath as NSString).pathExtension.lowercased()
+        let defaultFormat = fileExtension == "md" || fileExtension == "markdown" ? "markdown" : "todotxt"
+        
+        let tasks: [ImportedTask]
+        switch formatOptions.format ?? defaultFormat {
+        case "todotxt", "todo.txt", "txt":
+            tasks = parseTodoTxt(content)
+        case "markdown", "md":
+            tasks = parseMarkdown(content)
+        case let format:
+            throw JDIError.usage("Unknown import format '\(format)'", usage: "jdi import <file> [--format todotxt|markdown]")
+        }
+        
+        let result = todoList.importTasks(tasks)
+        try commit("import \(args[2])")
+        print("Imported \(result.added) todo(s) and \(result.archived) completed task(s) from \(args[2])")
+        
+    case "reopen", "ro":
+        guard args.count == 3 else {
+            throw JDIError.usage("'reopen' requires a history reference", usage: "jdi reopen <h1|h2|...>")
+        }
+        guard let index = todoList.historyIndex(ref: args[2]) else {
+            throw JDIError.notFound("No history entry '\(args[2])'. Run 'jdi history' to see references")
+        }
+        
+        let task = todoList.completedHistory[index]
+        guard let id = todoList.reopenCompleted(at: index), let item = todoList.items[String(id)] else {
+            throw JDIError.failed("Could not reopen '\(args[2])'")
+        }
+        try commit("reopen \(args[2]): \(task.text)")
+        
+        if options.json {
+            try printJSON(["reopened": todoList.node(for: item)])
+        } else {
+            let subtaskInfo = task.subtasks.isEmpty ? "" : " and \(task.subtasks.count) subtask(s)"
+            print("↺ Reopened todo \(id)\(subtaskInfo): \(task.text)")
+            if task.subtasks.isEmpty && task.hadSubtasks {
+                print("Its \(task.subtaskCount) subtask(s) were completed before subtasks were archived and cannot be restored")
+            }
+        }
+        
+    case "trash":
+        let trashUsage = "jdi trash [empty | retention [<days>|never]]"
+        switch args.count > 2 ? args[2] : nil {
+        case nil:
+            if options.json {
+                try printJSON(todoList.trashEntries().map { TrashEntryOutput(ref: $0.ref, entry: $0.entry) })
+            } else {
+                todoList.displayTrash(retentionDays: store.trashRetentionDays)
+            }
+            
+        case "empty"?:
+            guard !todoList.trash.isEmpty else {
+                print("Trash is already empty.")
+                return
+            }
+            
+            let count = todoList.trash.count
+            todoList.trash.removeAll()
+            store.lists[listName] = todoList
+            
+            // Emptying is permanent, so it is not journaled and the trash is
+            // cleared from this list's undo history as well.
+            var journal = loadJournal(paths)
+            journal.updateSnapshots { name, snapshot in
+                if name == listName {
+                    snapshot.trash.removeAll()
+                }
+            }
+            try saveTodoStore(store, paths)
+            try saveJournal(journal, paths)
+            print("Emptied the trash (\(count) deleted todo(s))")
+            
+        case "retention"?:
+            guard args.count <= 4 else {
+                throw JDIError.usage("Too many arguments for 'trash retention'", usage: trashUsage)
+            }
+            guard args.count == 4 else {
+                let days = store.trashRetentionDays
+                print(days > 0 ? "Deleted todos are kept for \(days) days" : "Deleted todos are kept until the trash is emptied")
+                return
+            }
+            
+            if args[3] == "never" {
+                store.trashRetentionDays = 0
+            } else if let days = Int(args[3]), days > 0 {
+                store.trashRetentionDays = days
+            } else {
+                throw JDIError.usage("Invalid retention '\(args[3])'", usage: trashUsage)
+            }
+            try saveTodoStore(store, paths)
+            print(store.trashRetentionDays > 0 ? "Deleted todos will be kept for \(store.trashRetentionDays) days" : "Deleted todos will be kept until the trash is emptied")
+            
+        case let subcommand?:
+            throw JDIError.usage("Unknown trash command '\(subcommand)'", usage: trashUsage)
+        }
+        
+    case "restore":
+        guard args.count == 3 else {
+            throw JDIError.usage("'restore' requires a trash reference", usage: "jdi restore <t1|t2|...>")
+        }
+        guard let index = todoList.trashIndex(ref: args[2]) else {
+            throw JDIError.notFound("No trash entry '\(args[2])'. Run 'jdi trash' to see references")
+        }
+        
+        let entry = todoList.trash[index]
+        guard let id = todoList.restoreTrashed(at: index), let item = todoList.items[String(id)] else {
+            throw JDIError.failed("Could not restore '\(args[2])'")
+        }
+        try commit("restore \(args[2]): \(item.text)")
+        
+        if options.json {
+            try printJSON(["restored": todoList.node(for: item)])
+        } else {
+            let subtaskCount = entry.items.count - 1
+            let subtaskInfo = subtaskCount > 0 ? " and \(subtaskCount) subtask(s)" : ""
+            print("Restored todo \(id)\(subtaskInfo): \(item.text)")
+        }
+        
+    case "history", "hist":
+        let historyUsage = "jdi history [<count> | --all]"
+        var limit: Int? = 20
+        if args.count == 3 {
+            if args[2] == "--all" || args[2] == "-a" {
+                limit = nil
+            } else if let count = Int(args[2]), count > 0 {
+                limit = count
+            } else {
+                throw JDIError.usage("Invalid count '\(args[2])'", usage: historyUsage)
+            }
+        } else if args.count > 3 {
+            throw JDIError.usage("Too many arguments for 'history'", usage: historyUsage)
+        }
+        
+        if options.json {
+            let entries = todoList.historyEntries()
+            try printJSON(entries.prefix(limit ?? entries.count).map { HistoryEntryOutput(ref: $0.ref, task: $0.task) })
+        } else {
+            todoList.displayHistory(limit: limit)
+        }
+        
     case "stats", "st":
-        todoList.displayStats()
+        let range = try parseStatsOptions(args[2...])
+        let stats = CompletionStats(history: todoList.completedHistory, since: range.since, until: range.until)
+        if options.json {
+            try printJSON(StatsOutput(completedCount: todoList.completedCount, stats: stats))
+        } else {
+            stats.display(completedCount: todoList.completedCount)
+        }
+        
+    case "tui":
+        // The TUI takes the lock for each change instead of for its whole run.
+        lock.release()
+        try TodoTUI(paths: paths, listName: listName).run()
         
     case "help", "--help", "-h", "h":
         printUsage()
         
     default:
-        print("Error: Unknown command '\(command)'")
-        printUsage()
+        throw JDIError.usage("Unknown command '\(command)'")
+    }
+}
+
+func main() {
+    do {
+        try run(CommandLine.arguments)
+    } catch let error as JDIError {
+        printError("Error: \(error)")
+        if case .usage(_, let usage) = error {
+            printError(usage.map { "Usage: \($0)" } ?? "Run 'jdi help' for usage.")
+        }
+        exit(error.exitCode)
+    } catch {
+        printError("Error: \(error)")
+        exit(1)
     }
 }
 
@@ -0,0 +1,108 @@
+import XCTest
+@testable import JustDooooooIt
+
+final class MigrationTests: XCTestCase {
+    private func fixture(_ name: String) throws -> JSONObject {
+        let url = try XCTUnwrap(Bundle.module.url(forResource: name, withExtension: "json", subdirectory: "Fixtures"))
+        return try parseStoreJSON(Data(contentsOf: url))
+    }
+    
+    private func decodeStore(_ json: JSONObject) throws -> TodoStore {
+        let data = try JSONSerialization.data(withJSONObject: json)
+        return try JSONDecoder().decode(TodoStore.self, from: data)
+    }
+    
+    func testVersion0RunsEveryStep() throws {
+        var json = try fixture("v0")
+        XCTAssertEqual(schemaVersion(of: json), 0)
+        
+        let applied = migrateStoreJSON(&json)
+        XCTAssertEqual(applied.map { $0.migration.version }, [1, 2])
+        XCTAssertEqual(schemaVersion(of: json), currentSchemaVersion)
+        
+        let store = try decodeStore(json)
+        XCTAssertEqual(store.currentList, TodoStore.defaultListName)
+        XCTAssertEqual(store.trashRetentionDays, TodoStore.defaultTrashRetentionDays)
+        let list = try XCTUnwrap(store.lists[TodoStore.defaultListName])
+        XCTAssertEqual(list.nextId, 4)
+        XCTAssertEqual(list.items["1"]?.position, 1)
+        XCTAssertEqual(list.items["1"]?.tags, ["#trip"])
+        XCTAssertEqual(list.items["2"]?.parentId, 1)
+        XCTAssertEqual(list.items["2"]?.dueDate, "2024-02-01")
+        XCTAssertEqual(list.completedHistory.first?.subtasks.count, 0)
+        XCTAssertTrue(list.trash.isEmpty)
+    }
+    
+    func testVersion1KeepsListsAndSelection() throws {
+        var json = try fixture("v1")
+        XCTAssertEqual(schemaVersion(of: json), 1)
+        
+        let applied = migrateStoreJSON(&json)
+        XCTAssertEqual(applied.map { $0.migration.version }, [2])
+        
+        let store = try decodeStore(json)
+        XCTAssertEqual(store.currentList, "work")
+        XCTAssertEqual(store.calendarSync["work"], "/Users/jdi/Calendars/work.ics")
+        XCTAssertEqual(store.trashRetentionDays, TodoStore.defaultTrashRetentionDays)
+        XCTAssertEqual(store.lists["work"]?.items["2"]?.tags, ["#release"])
+        XCTAssertEqual(store.lists["work"]?.completedCount, 0)
+        XCTAssertEqual(store.lists[TodoStore.defaultListName]?.items["1"]?.position, 1)
+    }
+    
+    func testBackupIsOfferedBeforeEachStep() throws {
+        var json = try fixture("v0")
+        var versions: [Int] = []
+        migrateStoreJSON(&json) { version, stepJSON in
+            XCTAssertEqual(schemaVersion(of: stepJSON), version)
+            versions.append(version)
+        }
+        XCTAssertEqual(versions, [0, 1])
+    }
+    
+    func testCurrentVersionRunsNoSteps() throws {
+        var json = try fixture("v1")
+        migrateStoreJSON(&json)
+        let migrated = try JSONSerialization.data(withJSONObject: json, options: .sortedKeys)
+        
+        XCTAssertTrue(migrateStoreJSON(&json).isEmpty)
+        XCTAssertEqual(try JSONSerialization.data(withJSONObject: json, options: .sortedKeys), migrated)
+    }
+    
+    func testMissingFieldsAreFilledOnCurrentVersion() throws {
+        var json = try fixture("v1")
+        migrateStoreJSON(&json)
+        json["trash_retention_days"] = nil
+        var lists = try XCTUnwrap(json["lists"] as? JSONObject)
+        lists["work"] = ["items": JSONObject(), "next_id": 3]
+        json["lists"] = lists
+        XCTAssertThrowsError(try decodeStore(json))
+        
+        fillStoreDefaults(&json)
+        let store = try decodeStore(json)
+        XCTAssertEqual(store.trashRetentionDays, TodoStore.defaultTrashRetentionDays)
+        XCTAssertEqual(store.lists["work"]?.nextId, 3)
+        XCTAssertEqual(store.lists["work"]?.trash.isEmpty, true)
+    }
+    
+    func testNewerVersionIsRejected() {
+        XCTAssertThrowsError(try checkSchemaVersion(currentSchemaVersion + 1, of: "todos.json"))
+        XCTAssertNoThrow(try checkSchemaVersion(currentSchemaVersion, of: "todos.json"))
+    }
+    
+    func testJournalSnapshotsAreUpgraded() throws {
+        var json = try fixture("journal")
+        upgradeJournalJSON(&json)
+        let data = try JSONSerialization.data(withJSONObject: json)
+        let journal = try JSONDecoder().decode(Journal.self, from: data)
+        
+        let undone = try XCTUnwrap(journal.undoStack.first?.snapshots[TodoStore.defaultListName])
+        XCTAssertEqual(undone.items["1"]?.tags, ["#trip"])
+        XCTAssertEqual(undone.items["1"]?.position, 1)
+        XCTAssertEqual(undone.completedCount, 0)
+        
+        let redone = try XCTUnwrap(journal.redoStack.first?.snapshots["work"])
+        XCTAssertEqual(redone.nextId, 5)
+        XCTAssertTrue(redone.completedHistory.isEmpty)
+        XCTAssertTrue(redone.trash.isEmpty)
+    }
+}